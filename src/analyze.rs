@@ -0,0 +1,229 @@
+use pico8_rom::lua::{self, Kind, Token};
+use pico8_rom::rom::{self, GFX};
+use pico8_rom::sfx::Instrument;
+use pico8_rom::Cartridge;
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// List SFX, sprites, and music patterns that nothing in the ROM refers to.
+/// Code is only searched for `sfx`, `music`, and `spr` calls with a number as the first argument,
+/// so anything played or drawn through a variable will show up as unused.
+pub fn analyze(path: &Path) -> anyhow::Result<()> {
+    let cartridge = Cartridge::read(path)?;
+    let music = cartridge.music()?;
+    let sfx = cartridge.sfx()?;
+    let code = cartridge.code()?;
+    let tokens = code_tokens(&code);
+
+    let sfx_calls = Calls::find(&tokens, b"sfx");
+    let music_calls = Calls::find(&tokens, b"music");
+    let spr_calls = Calls::find(&tokens, b"spr");
+
+    let mut used_sfx = sfx_calls.literals.clone();
+    used_sfx.extend(music.sfx_usage().keys().map(|id| *id as usize));
+    for (i, s) in sfx.sfxes.iter().enumerate() {
+        if !used_sfx.contains(&i) {
+            continue;
+        }
+        for note in s.notes.iter().filter(|n| u8::from(n.volume()) > 0) {
+            if let Instrument::Sfx(id) = note.instrument() {
+                used_sfx.insert(u8::from(id) as usize);
+            }
+        }
+    }
+    let unused_sfx = (0..sfx.sfxes.len())
+        .filter(|i| sfx.sfxes[*i].enabled() && !used_sfx.contains(i))
+        .collect::<Vec<_>>();
+
+    let unused_sprites = unused_sprites(&cartridge, &spr_calls)?;
+
+    let played = music_calls
+        .literals
+        .iter()
+        .filter(|start| **start < music.patterns.len())
+        .flat_map(|start| music.play(*start).patterns)
+        .collect::<BTreeSet<_>>();
+    let orphan_patterns = (0..music.patterns.len())
+        .filter(|p| music.patterns[*p].enabled() && !played.contains(p))
+        .collect::<Vec<_>>();
+
+    print_list("Unused SFX", &unused_sfx);
+    print_list("Unused sprites", &unused_sprites);
+    print_list("Orphan patterns", &orphan_patterns);
+    for (name, calls) in [
+        ("sfx", &sfx_calls),
+        ("music", &music_calls),
+        ("spr", &spr_calls),
+    ] {
+        if calls.other > 0 {
+            println!(
+                "{} {}() call(s) without a literal number weren't counted",
+                calls.other, name
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Tokens of the code, or none if it can't be tokenized,
+/// so the music and map are still searched.
+fn code_tokens(code: &[u8]) -> Vec<Token<'_>> {
+    lua::tokenize(code).unwrap_or_else(|e| {
+        eprintln!(
+            "Warning: {}; calls in the code weren't counted, so anything only used from the code will show up as unused",
+            e
+        );
+        vec![]
+    })
+}
+
+/// Sprites with pixels that aren't drawn by `spr` or placed in the map,
+/// including the map rows that share memory with the bottom half of the sprite sheet.
+fn unused_sprites(cartridge: &Cartridge, spr_calls: &Calls) -> anyhow::Result<Vec<usize>> {
+    let gfx = cartridge.bytes(GFX)?;
+    let mut used_sprites = spr_calls.literals.clone();
+    used_sprites.extend(
+        cartridge
+            .map()?
+            .iter()
+            .filter(|cell| **cell != 0)
+            .map(|cell| *cell as usize),
+    );
+    Ok((0..256)
+        .filter(|sprite| {
+            !used_sprites.contains(sprite)
+                && rom::sprite_rows(*sprite).any(|row| gfx[row].iter().any(|b| *b != 0))
+        })
+        .collect())
+}
+
+fn print_list(name: &str, items: &[usize]) {
+    let items = items
+        .iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    println!(
+        "{}: {}",
+        name,
+        if items.is_empty() { "none" } else { &items }
+    );
+}
+
+/// Calls to one function in Lua source.
+struct Calls {
+    /// First arguments that are non-negative number literals.
+    literals: BTreeSet<usize>,
+    /// Calls with any other first argument.
+    other: usize,
+}
+
+impl Calls {
+    /// Calls to `name` in tokenized code, so calls in strings and comments don't count.
+    fn find(tokens: &[Token], name: &[u8]) -> Calls {
+        let mut calls = Calls {
+            literals: BTreeSet::new(),
+            other: 0,
+        };
+        let tokens = tokens
+            .iter()
+            .filter(|t| t.kind != Kind::Comment)
+            .collect::<Vec<_>>();
+        let is = |i: usize, kind: Kind, text: &[u8]| {
+            tokens
+                .get(i)
+                .is_some_and(|t| t.kind == kind && t.text == text)
+        };
+        for i in 0..tokens.len() {
+            let is_method =
+                i > 0 && (is(i - 1, Kind::Punctuation, b".") || is(i - 1, Kind::Punctuation, b":"));
+            if !is(i, Kind::Name, name) || is_method || !is(i + 1, Kind::Punctuation, b"(") {
+                continue;
+            }
+            let arg = i + 2;
+            let (negative, arg) = match is(arg, Kind::Punctuation, b"-") {
+                true => (true, arg + 1),
+                false => (false, arg),
+            };
+            let literal = tokens
+                .get(arg)
+                .filter(|t| t.kind == Kind::Number)
+                .and_then(|t| number(t.text))
+                .filter(|_| {
+                    is(arg + 1, Kind::Punctuation, b",") || is(arg + 1, Kind::Punctuation, b")")
+                });
+            match literal {
+                Some(n) if !negative => {
+                    calls.literals.insert(n);
+                }
+                Some(_) => {}
+                None => calls.other += 1,
+            }
+        }
+        calls
+    }
+}
+
+/// Value of a decimal or `0x` hex integer literal.
+fn number(text: &[u8]) -> Option<usize> {
+    let text = std::str::from_utf8(text).ok()?;
+    match text.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pico8_rom::rom::MAP_SHARED;
+
+    fn calls(code: &str, name: &[u8]) -> Calls {
+        Calls::find(&lua::tokenize(code.as_bytes()).unwrap(), name)
+    }
+
+    #[test]
+    fn calls_in_strings_and_comments_dont_count() {
+        let code = "sfx(1) sfx( 0x2 , 1)\n\
+                    print(\"sfx(3)\") print([[sfx(4)]])\n\
+                    -- sfx(5)\n\
+                    --[[ sfx(6)\n\
+                    sfx(7) ]]\n\
+                    a.sfx(8) a:sfx(9) mysfx(10)\n\
+                    sfx(n) sfx(-1)";
+        let calls = calls(code, b"sfx");
+        assert_eq!(calls.literals, BTreeSet::from([1, 2]));
+        assert_eq!(calls.other, 1);
+    }
+
+    #[test]
+    fn code_that_cant_be_tokenized_still_gets_the_other_checks() {
+        assert!(code_tokens(b"print(\"unfinished").is_empty());
+
+        let mut cartridge = Cartridge::from_bytes(vec![0; Cartridge::LEN]).unwrap();
+        cartridge.set_code(b"sfx(1) print(\"unfinished").unwrap();
+        let path = std::env::temp_dir().join(format!(
+            "pico8_rom_tool_analyze_{}.p8.rom",
+            std::process::id()
+        ));
+        cartridge.write(&path).unwrap();
+        let result = analyze(&path);
+        std::fs::remove_file(&path).unwrap();
+        result.unwrap();
+    }
+
+    #[test]
+    fn map_rows_shared_with_sprites_use_sprites() {
+        let mut cartridge = Cartridge::from_bytes(vec![0; Cartridge::LEN]).unwrap();
+        for sprite in [1, 2, 3] {
+            let row = rom::sprite_rows(sprite).next().unwrap();
+            cartridge.bytes_mut(row).unwrap()[0] = 0x11;
+        }
+        // Sprite 1 is in map row 0, and sprite 2 is in map row 48.
+        cartridge.bytes_mut(rom::MAP).unwrap()[5] = 1;
+        cartridge.bytes_mut(MAP_SHARED).unwrap()[0x800] = 2;
+        let spr_calls = calls("spr(3,0,0)", b"spr");
+        // That map cell is also the first pixels of sprite 192, which nothing uses.
+        assert_eq!(unused_sprites(&cartridge, &spr_calls).unwrap(), [192]);
+    }
+}
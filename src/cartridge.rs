@@ -0,0 +1,260 @@
+use crate::code;
+use crate::error::{Error, Result};
+use crate::gfx::LABEL_SIZE;
+use crate::music::Section as MusicSection;
+use crate::rom::{CODE, MAP, MAP_SHARED, MUSIC, SFX};
+use crate::sfx::Section as SfxSection;
+use crate::{p8, p8png};
+use packed_struct::types::bits::ByteArray;
+use packed_struct::PackedStruct;
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::path::Path;
+
+/// A PICO-8 ROM held in memory, in `.p8.rom` layout, and the cart's label if it has one.
+/// See https://pico-8.fandom.com/wiki/Memory
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cartridge {
+    rom: Vec<u8>,
+    label: Option<Vec<u8>>,
+}
+
+/// Ways PICO-8 saves a cartridge, chosen by file extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileFormat {
+    /// `.p8.rom`: just the ROM. Standard input and output are always ROMs.
+    Rom,
+    /// `.p8`: text.
+    P8,
+    /// `.p8.png`: an image of a cartridge with the ROM hidden in it.
+    Png,
+}
+
+impl FileFormat {
+    pub fn from_path(path: &Path) -> Result<FileFormat> {
+        if is_stdio(path) {
+            return Ok(FileFormat::Rom);
+        }
+        let name = path
+            .file_name()
+            .map(|x| x.to_string_lossy())
+            .unwrap_or_default();
+        if name.ends_with(".p8.rom") {
+            Ok(FileFormat::Rom)
+        } else if name.ends_with(".p8") {
+            Ok(FileFormat::P8)
+        } else if name.ends_with(".p8.png") {
+            Ok(FileFormat::Png)
+        } else {
+            Err(Error::FileFormat(path.to_string_lossy().to_string()))
+        }
+    }
+}
+
+impl Cartridge {
+    /// Every ROM is exactly this long.
+    pub const LEN: usize = CODE.end;
+
+    pub fn from_bytes(rom: Vec<u8>) -> Result<Cartridge> {
+        if rom.len() != Self::LEN {
+            return Err(Error::Count {
+                name: "ROM bytes",
+                expected: Self::LEN,
+                actual: rom.len(),
+            });
+        }
+        Ok(Cartridge { rom, label: None })
+    }
+
+    pub fn from_slice(rom: &[u8]) -> Result<Cartridge> {
+        Self::from_bytes(rom.to_vec())
+    }
+
+    /// Read a whole ROM from a stream.
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Cartridge> {
+        let mut rom = Vec::with_capacity(Self::LEN);
+        reader.read_to_end(&mut rom)?;
+        Self::from_bytes(rom)
+    }
+
+    /// Read a `.p8.rom`, `.p8`, or `.p8.png` file, or a ROM from standard input if the path is `-`.
+    pub fn read(path: &Path) -> Result<Cartridge> {
+        Self::read_as(path, FileFormat::from_path(path)?)
+    }
+
+    /// Read a file in the given format whatever its name, like the temporary files git merges.
+    pub fn read_as(path: &Path, format: FileFormat) -> Result<Cartridge> {
+        match format {
+            FileFormat::Rom if is_stdio(path) => Self::from_reader(std::io::stdin().lock()),
+            FileFormat::Rom => Self::from_bytes(std::fs::read(path)?),
+            FileFormat::P8 => p8::parse(&std::fs::read_to_string(path)?),
+            FileFormat::Png => p8png::parse(&std::fs::read(path)?),
+        }
+    }
+
+    /// Write a `.p8.rom`, `.p8`, or `.p8.png` file, or a ROM to standard output if the path is `-`.
+    /// ROMs don't have labels, so writing one leaves the label out.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        self.write_as(path, FileFormat::from_path(path)?)
+    }
+
+    /// Write a file in the given format whatever its name.
+    pub fn write_as(&self, path: &Path, format: FileFormat) -> Result<()> {
+        match format {
+            FileFormat::Rom if is_stdio(path) => std::io::stdout().lock().write_all(&self.rom)?,
+            FileFormat::Rom => std::fs::write(path, &self.rom)?,
+            FileFormat::P8 => std::fs::write(path, p8::write(self)?)?,
+            FileFormat::Png => std::fs::write(path, p8png::write(self)?)?,
+        }
+        Ok(())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.rom
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.rom
+    }
+
+    /// Raw bytes of a part of the ROM that doesn't have a section type.
+    pub fn bytes(&self, range: Range<usize>) -> Result<&[u8]> {
+        let actual = self.rom.len();
+        self.rom.get(range.clone()).ok_or(Error::Count {
+            name: "ROM bytes",
+            expected: range.end,
+            actual,
+        })
+    }
+
+    pub fn bytes_mut(&mut self, range: Range<usize>) -> Result<&mut [u8]> {
+        let actual = self.rom.len();
+        self.rom.get_mut(range.clone()).ok_or(Error::Count {
+            name: "ROM bytes",
+            expected: range.end,
+            actual,
+        })
+    }
+
+    pub fn section<T: PackedStruct>(&self, offset: usize) -> Result<T> {
+        let mut buf = T::ByteArray::new(0);
+        let len = buf.as_bytes_slice().len();
+        buf.as_mut_bytes_slice()
+            .copy_from_slice(self.bytes(offset..offset + len)?);
+        Ok(T::unpack(&buf)?)
+    }
+
+    pub fn set_section<T: PackedStruct>(&mut self, offset: usize, section: &T) -> Result<()> {
+        let packed = section.pack()?;
+        let bytes = packed.as_bytes_slice();
+        self.bytes(offset..offset + bytes.len())?;
+        self.rom[offset..offset + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    pub fn music(&self) -> Result<MusicSection> {
+        self.section(MUSIC.start)
+    }
+
+    pub fn set_music(&mut self, music: &MusicSection) -> Result<()> {
+        self.set_section(MUSIC.start, music)
+    }
+
+    pub fn sfx(&self) -> Result<SfxSection> {
+        self.section(SFX.start)
+    }
+
+    pub fn set_sfx(&mut self, sfx: &SfxSection) -> Result<()> {
+        self.set_section(SFX.start, sfx)
+    }
+
+    /// All 128×64 map cells, row by row, including the bottom half shared with the sprite sheet.
+    pub fn map(&self) -> Result<Vec<u8>> {
+        Ok([self.bytes(MAP)?, self.bytes(MAP_SHARED)?].concat())
+    }
+
+    /// Color of each pixel of the 128×128 label, where 16–31 are the extra palette.
+    pub fn label(&self) -> Option<&[u8]> {
+        self.label.as_deref()
+    }
+
+    pub fn set_label(&mut self, pixels: Vec<u8>) -> Result<()> {
+        if pixels.len() != LABEL_SIZE * LABEL_SIZE {
+            return Err(Error::Count {
+                name: "label pixels",
+                expected: LABEL_SIZE * LABEL_SIZE,
+                actual: pixels.len(),
+            });
+        }
+        if let Some(color) = pixels.iter().find(|c| **c > 31) {
+            return Err(Error::LabelColor(*color));
+        }
+        self.label = Some(pixels);
+        Ok(())
+    }
+
+    pub fn remove_label(&mut self) {
+        self.label = None;
+    }
+
+    /// Lua source, decompressed, as P8SCII bytes.
+    pub fn code(&self) -> Result<Vec<u8>> {
+        code::decompress(self.bytes(CODE)?)
+    }
+
+    /// Compress Lua source into the code section.
+    pub fn set_code(&mut self, code: &[u8]) -> Result<()> {
+        let compressed = code::compress(code)?;
+        if compressed.len() > CODE.len() {
+            return Err(Error::CodeTooLong(compressed.len()));
+        }
+        let section = self.bytes_mut(CODE)?;
+        section.fill(0);
+        section[..compressed.len()].copy_from_slice(&compressed);
+        Ok(())
+    }
+}
+
+fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom;
+    use std::io::Cursor;
+
+    #[test]
+    fn sections_round_trip_through_bytes() {
+        let mut cartridge = Cartridge::from_slice(&[0; Cartridge::LEN]).unwrap();
+        let mut sfx = cartridge.sfx().unwrap();
+        sfx.sfxes[3].speed = 12;
+        cartridge.set_sfx(&sfx).unwrap();
+
+        let bytes = cartridge.clone().into_bytes();
+        assert_eq!(12, bytes[SFX.start + 3 * 68 + 65]);
+        let streamed =
+            rom::read_section_from::<SfxSection, _>(&mut Cursor::new(&bytes), SFX.start as u64)
+                .unwrap();
+        assert_eq!(12, streamed.sfxes[3].speed);
+        assert_eq!(cartridge, Cartridge::from_reader(bytes.as_slice()).unwrap());
+    }
+
+    #[test]
+    fn wrong_length_is_an_error() {
+        assert!(Cartridge::from_slice(&[0; 100]).is_err());
+    }
+
+    #[test]
+    fn unknown_formats_and_missing_files_are_typed_errors() {
+        assert!(matches!(
+            Cartridge::read(Path::new("cart.txt")),
+            Err(Error::FileFormat(path)) if path == "cart.txt"
+        ));
+        let error = Cartridge::read(Path::new("/nonexistent/cart.p8.rom")).unwrap_err();
+        assert!(matches!(&error, Error::Io(e) if e.kind() == std::io::ErrorKind::NotFound));
+        let source = std::error::Error::source(&error).unwrap();
+        assert!(source.downcast_ref::<std::io::Error>().is_some());
+    }
+}
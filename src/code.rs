@@ -0,0 +1,366 @@
+use crate::error::{Error, Result};
+use std::collections::HashMap;
+
+/// Decode the code section, which may be plain text or either of PICO-8's compressed formats.
+/// See https://pico-8.fandom.com/wiki/P8PNGFileFormat#Code_compression
+pub fn decompress(bytes: &[u8]) -> Result<Vec<u8>> {
+    if bytes.starts_with(b":c:\0") {
+        decompress_legacy(bytes)
+    } else if bytes.starts_with(b"\0pxa") {
+        decompress_pxa(bytes)
+    } else {
+        let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+        Ok(bytes[..end].to_vec())
+    }
+}
+
+/// Decompressed length, stored big-endian after the 4-byte header.
+fn length(bytes: &[u8]) -> Result<usize> {
+    match bytes.get(4..6) {
+        Some(len) => Ok(u16::from_be_bytes([len[0], len[1]]) as usize),
+        None => Err(Error::Code("header is truncated")),
+    }
+}
+
+/// Characters that the legacy format encodes in one byte, from 0x01 up.
+const LEGACY_CHARS: &[u8] = b"\n 0123456789abcdefghijklmnopqrstuvwxyz!#%(){}[]<>+=/*:;.,~_";
+
+/// Format used before PICO-8 0.2.0.
+fn decompress_legacy(bytes: &[u8]) -> Result<Vec<u8>> {
+    let len = length(bytes)?;
+    let mut out = Vec::with_capacity(len);
+    let mut input = bytes.iter().skip(8).copied();
+    let mut next = || input.next().ok_or(Error::Code("data ends early"));
+    while out.len() < len {
+        match next()? {
+            0x00 => out.push(next()?),
+            b @ 0x01..=0x3b => out.push(LEGACY_CHARS[b as usize - 1]),
+            b => {
+                let b2 = next()?;
+                let offset = (b as usize - 0x3c) * 16 + (b2 & 0xf) as usize;
+                let length = (b2 >> 4) as usize + 2;
+                copy_back(&mut out, offset, length)?;
+            }
+        }
+    }
+    out.truncate(len);
+    Ok(out)
+}
+
+/// Move-to-front and back reference format used since PICO-8 0.2.0.
+fn decompress_pxa(bytes: &[u8]) -> Result<Vec<u8>> {
+    let len = length(bytes)?;
+    let mut out = Vec::with_capacity(len);
+    let mut bits = Bits {
+        bytes: &bytes[8.min(bytes.len())..],
+        pos: 0,
+    };
+    let mut mtf = (0..=255u8).collect::<Vec<_>>();
+    while out.len() < len {
+        if bits.read(1)? == 1 {
+            let mut unary = 0;
+            while bits.read(1)? == 1 {
+                unary += 1;
+            }
+            let index = bits.read(4 + unary)? as usize + (((1 << unary) - 1) << 4);
+            if index >= mtf.len() {
+                return Err(Error::Code("character index is out of range"));
+            }
+            let c = mtf.remove(index);
+            mtf.insert(0, c);
+            out.push(c);
+        } else {
+            let offset_bits = if bits.read(1)? == 1 {
+                5
+            } else if bits.read(1)? == 1 {
+                10
+            } else {
+                15
+            };
+            let offset = bits.read(offset_bits)? as usize + 1;
+            if offset_bits == 10 && offset == 1 {
+                // Offset 1 fits in 5 bits, so this marks a run of uncompressed bytes instead.
+                loop {
+                    match bits.read(8)? as u8 {
+                        0 => break,
+                        c => out.push(c),
+                    }
+                }
+            } else {
+                let mut length = 3;
+                loop {
+                    let part = bits.read(3)? as usize;
+                    length += part;
+                    if part != 7 {
+                        break;
+                    }
+                }
+                copy_back(&mut out, offset, length)?;
+            }
+        }
+    }
+    out.truncate(len);
+    Ok(out)
+}
+
+/// Encode code in the PXA format, as PICO-8 0.2.0 and later save it.
+pub fn compress(code: &[u8]) -> Result<Vec<u8>> {
+    let len = u16::try_from(code.len()).map_err(|_| Error::CodeTooLong(code.len()))?;
+    let mut bits = BitWriter::default();
+    let mut mtf = (0..=255u8).collect::<Vec<_>>();
+    let mut matches = Matches::default();
+    let mut i = 0;
+    while i < code.len() {
+        match matches.longest(code, i) {
+            Some((offset, length))
+                if backref_cost(offset, length) < literal_cost(&mtf, &code[i..i + length]) =>
+            {
+                bits.write(1, 0);
+                let offset = offset as u32 - 1;
+                if offset < 1 << 5 {
+                    bits.write(1, 1);
+                    bits.write(5, offset);
+                } else if offset < 1 << 10 {
+                    bits.write(2, 0b10);
+                    bits.write(10, offset);
+                } else {
+                    bits.write(2, 0b00);
+                    bits.write(15, offset);
+                }
+                let mut rest = length - 3;
+                loop {
+                    let part = rest.min(7);
+                    bits.write(3, part as u32);
+                    rest -= part;
+                    if part != 7 {
+                        break;
+                    }
+                }
+                for j in i..i + length {
+                    matches.insert(code, j);
+                }
+                i += length;
+            }
+            _ => {
+                let index = mtf.iter().position(|c| *c == code[i]).unwrap_or_default();
+                let unary = literal_unary(index);
+                bits.write(1, 1);
+                bits.write(unary, (1 << unary) - 1);
+                bits.write(1, 0);
+                bits.write(4 + unary, (index - (((1 << unary) - 1) << 4)) as u32);
+                let c = mtf.remove(index);
+                mtf.insert(0, c);
+                matches.insert(code, i);
+                i += 1;
+            }
+        }
+    }
+    let mut out = b"\0pxa".to_vec();
+    out.extend(len.to_be_bytes());
+    let total = 8 + bits.bytes.len();
+    let total = u16::try_from(total).map_err(|_| Error::CodeTooLong(total))?;
+    out.extend(total.to_be_bytes());
+    out.extend(bits.bytes);
+    Ok(out)
+}
+
+/// Number of unary bits before a move-to-front index: 0–15 take none, 16–47 take one, and so on.
+fn literal_unary(index: usize) -> u32 {
+    let mut unary = 0;
+    while index >= ((1 << (unary + 1)) - 1) << 4 {
+        unary += 1;
+    }
+    unary
+}
+
+/// Bits needed to write `bytes` as literals, ignoring how they reorder the move-to-front list.
+fn literal_cost(mtf: &[u8], bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .map(|b| {
+            let index = mtf.iter().position(|c| c == b).unwrap_or_default();
+            6 + 2 * literal_unary(index)
+        })
+        .sum()
+}
+
+fn backref_cost(offset: usize, length: usize) -> u32 {
+    let offset_bits = match offset {
+        0..=32 => 6,
+        33..=1024 => 12,
+        _ => 17,
+    };
+    1 + offset_bits + 3 * ((length as u32 - 3) / 7 + 1)
+}
+
+/// Earlier positions of each 3-byte sequence, for finding back references.
+#[derive(Default)]
+struct Matches {
+    positions: HashMap<[u8; 3], Vec<usize>>,
+}
+
+impl Matches {
+    /// Back references can reach 32768 bytes.
+    const WINDOW: usize = 1 << 15;
+    /// How many earlier positions to try, newest first.
+    const TRIES: usize = 256;
+
+    fn insert(&mut self, code: &[u8], i: usize) {
+        if let Some(key) = code.get(i..i + 3) {
+            self.positions
+                .entry([key[0], key[1], key[2]])
+                .or_default()
+                .push(i);
+        }
+    }
+
+    /// Offset and length of the longest earlier copy of the bytes at `i`.
+    fn longest(&self, code: &[u8], i: usize) -> Option<(usize, usize)> {
+        let key = code.get(i..i + 3)?;
+        let positions = self.positions.get(&[key[0], key[1], key[2]])?;
+        positions
+            .iter()
+            .rev()
+            .take_while(|p| i - **p <= Self::WINDOW)
+            .take(Self::TRIES)
+            .map(|p| {
+                let length = code[i..]
+                    .iter()
+                    .zip(code[*p..].iter())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                (i - p, length)
+            })
+            .max_by_key(|(offset, length)| (*length, usize::MAX - offset))
+    }
+}
+
+fn copy_back(out: &mut Vec<u8>, offset: usize, length: usize) -> Result<()> {
+    if offset == 0 || offset > out.len() {
+        return Err(Error::Code("back reference is out of range"));
+    }
+    let start = out.len() - offset;
+    for i in start..start + length {
+        out.push(out[i]);
+    }
+    Ok(())
+}
+
+/// Bit reader for the PXA format, which reads the low bits of each byte first.
+struct Bits<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Bits<'_> {
+    fn read(&mut self, count: u32) -> Result<u32> {
+        let mut val = 0;
+        for i in 0..count {
+            let byte = self
+                .bytes
+                .get(self.pos / 8)
+                .ok_or(Error::Code("data ends early"))?;
+            val |= ((*byte as u32 >> (self.pos % 8)) & 1) << i;
+            self.pos += 1;
+        }
+        Ok(val)
+    }
+}
+
+/// Bit writer for the PXA format, which fills the low bits of each byte first.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    pos: usize,
+}
+
+impl BitWriter {
+    fn write(&mut self, count: u32, val: u32) {
+        for i in 0..count {
+            if self.pos.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            if let Some(byte) = self.bytes.last_mut() {
+                *byte |= (((val >> i) & 1) as u8) << (self.pos % 8);
+            }
+            self.pos += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_code_stops_at_nul() {
+        assert_eq!(b"cls()".to_vec(), decompress(b"cls()\0\0junk").unwrap());
+    }
+
+    #[test]
+    fn legacy_code_decompresses() {
+        // "ab" from the table, a literal "A", then a back reference to "ab" repeated.
+        let bytes = b":c:\0\0\x07\0\0\x0d\x0e\0A\x3c\x33";
+        assert_eq!(b"abAabAa".to_vec(), decompress(bytes).unwrap());
+    }
+
+    /// Bit writer for building PXA test data.
+    fn pack(fields: &[(u32, u32)]) -> Vec<u8> {
+        let mut bytes = vec![];
+        let mut pos = 0;
+        for (count, val) in fields {
+            for i in 0..*count {
+                if pos % 8 == 0 {
+                    bytes.push(0);
+                }
+                *bytes.last_mut().unwrap() |= (((val >> i) & 1) as u8) << (pos % 8);
+                pos += 1;
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn pxa_code_decompresses() {
+        let mut bytes = b"\0pxa\0\x07\0\0".to_vec();
+        bytes.extend(pack(&[
+            // 'a' (97) is at index 97 of the initial move-to-front list: 2 unary bits for 64–191.
+            (1, 1),
+            (1, 1),
+            (1, 1),
+            (1, 0),
+            (6, 97 - 48),
+            // 'b' (98) is still at index 98.
+            (1, 1),
+            (1, 1),
+            (1, 1),
+            (1, 0),
+            (6, 98 - 48),
+            // 'a' is now at index 1.
+            (1, 1),
+            (1, 0),
+            (4, 1),
+            // Back reference: 5-bit offset 2, length 4, repeating "ba".
+            (1, 0),
+            (1, 1),
+            (5, 1),
+            (3, 1),
+        ]));
+        assert_eq!(b"abababa".to_vec(), decompress(&bytes).unwrap());
+    }
+
+    #[test]
+    fn compressed_code_round_trips() {
+        let code = b"function _update()\n if btnp(4) then sfx(3) end\n if btnp(5) then sfx(4) end\nend\n\x8e\x97";
+        let compressed = compress(code).unwrap();
+        assert!(compressed.len() < code.len());
+        assert_eq!(code.to_vec(), decompress(&compressed).unwrap());
+        assert_eq!(b"".to_vec(), decompress(&compress(b"").unwrap()).unwrap());
+    }
+
+    #[test]
+    fn malformed_code_is_an_error() {
+        assert!(decompress(b":c:\0\0\x07\0\0\x3c\x33").is_err());
+        assert!(decompress(b"\0pxa\0\x07\0\0").is_err());
+    }
+}
@@ -0,0 +1,204 @@
+use diffy::DiffOptions;
+use pico8_rom::music::Pattern;
+use pico8_rom::rom::{self, GFF, GFX};
+use pico8_rom::sfx::{Note, Sfx};
+use pico8_rom::{p8scii, Cartridge};
+use std::fmt::Display;
+use std::path::Path;
+
+/// Print every change between two ROMs, one per line, in terms of SFX, patterns, sprites, and map cells,
+/// then a unified diff of the code.
+pub fn diff(a: &Path, b: &Path) -> anyhow::Result<()> {
+    let names = (a.to_string_lossy(), b.to_string_lossy());
+    let (a, b) = (Cartridge::read(a)?, Cartridge::read(b)?);
+    for change in changes(&a, &b, (&names.0, &names.1))? {
+        println!("{}", change);
+    }
+    Ok(())
+}
+
+fn changes(a: &Cartridge, b: &Cartridge, names: (&str, &str)) -> anyhow::Result<Vec<String>> {
+    let mut changes = vec![];
+
+    let music_a = a.music()?;
+    let music_b = b.music()?;
+    for (i, (a, b)) in music_a
+        .patterns
+        .iter()
+        .zip(music_b.patterns.iter())
+        .enumerate()
+    {
+        diff_pattern(i, a, b, &mut changes);
+    }
+
+    let sfx_a = a.sfx()?;
+    let sfx_b = b.sfx()?;
+    for (i, (a, b)) in sfx_a.sfxes.iter().zip(sfx_b.sfxes.iter()).enumerate() {
+        diff_sfx(i, a, b, &mut changes);
+    }
+
+    let gfx_a = a.bytes(GFX)?;
+    let gfx_b = b.bytes(GFX)?;
+    for sprite in 0..256 {
+        let pixels = sprite_pixels(gfx_a, sprite)
+            .zip(sprite_pixels(gfx_b, sprite))
+            .filter(|(a, b)| a != b)
+            .count();
+        if pixels > 0 {
+            changes.push(format!("sprite {} changed ({} pixels)", sprite, pixels));
+        }
+    }
+
+    let gff_a = a.bytes(GFF)?;
+    let gff_b = b.bytes(GFF)?;
+    for (sprite, (a, b)) in gff_a.iter().zip(gff_b.iter()).enumerate() {
+        changed(
+            &mut changes,
+            format!("sprite {} flags", sprite),
+            format!("{:08b}", a),
+            format!("{:08b}", b),
+        );
+    }
+
+    let map_a = a.map()?;
+    let map_b = b.map()?;
+    for (i, (a, b)) in map_a.iter().zip(map_b.iter()).enumerate() {
+        changed(
+            &mut changes,
+            format!("map cell ({},{})", i % 128, i / 128),
+            a,
+            b,
+        );
+    }
+
+    // The same code can be compressed differently, so compare it decompressed.
+    let code_a = p8scii::decode(&a.code()?);
+    let code_b = p8scii::decode(&b.code()?);
+    if code_a != code_b {
+        let patch = DiffOptions::new()
+            .set_original_filename(format!("{} code", names.0))
+            .set_modified_filename(format!("{} code", names.1))
+            .create_patch(&code_a, &code_b)
+            .to_string();
+        changes.extend(patch.lines().map(String::from));
+    }
+    Ok(changes)
+}
+
+/// Add a change like `what: a→b` if the values differ.
+fn changed<T: PartialEq + Display>(changes: &mut Vec<String>, what: String, a: T, b: T) {
+    if a != b {
+        changes.push(format!("{}: {}→{}", what, a, b));
+    }
+}
+
+/// Pixel colors of a sprite, left to right and top to bottom.
+fn sprite_pixels(gfx: &[u8], sprite: usize) -> impl Iterator<Item = u8> + '_ {
+    rom::sprite_rows(sprite)
+        .flat_map(move |row| gfx[row].iter().flat_map(|byte| [byte & 0xf, byte >> 4]))
+}
+
+fn diff_pattern(i: usize, a: &Pattern, b: &Pattern, changes: &mut Vec<String>) {
+    let what = |x: &str| format!("pattern {} {}", i, x);
+    changed(changes, what("loop start"), a.loop_start(), b.loop_start());
+    changed(changes, what("loop back"), a.loop_back(), b.loop_back());
+    changed(changes, what("stop"), a.stop_at_end(), b.stop_at_end());
+    for (c, (a, b)) in a.channels.iter().zip(b.channels.iter()).enumerate() {
+        let describe = |enabled: bool, sfx_id: u8| match enabled {
+            true => format!("sfx {}", sfx_id),
+            false => "off".to_string(),
+        };
+        changed(
+            changes,
+            what(&format!("channel {}", c)),
+            describe(a.enabled(), u8::from(a.sfx_id)),
+            describe(b.enabled(), u8::from(b.sfx_id)),
+        );
+    }
+}
+
+fn diff_sfx(i: usize, a: &Sfx, b: &Sfx, changes: &mut Vec<String>) {
+    let what = |x: &str| format!("SFX {} {}", i, x);
+    changed(changes, what("speed"), a.speed, b.speed);
+    changed(changes, what("loop start"), a.loop_start, b.loop_start);
+    changed(changes, what("loop end"), a.loop_end, b.loop_end);
+    changed(
+        changes,
+        what("filters"),
+        a.switches.filters().join(", "),
+        b.switches.filters().join(", "),
+    );
+    for (n, (a, b)) in a.notes.iter().zip(b.notes.iter()).enumerate() {
+        let note_changes = diff_note(a, b);
+        if !note_changes.is_empty() {
+            changes.push(format!("SFX {} note {}: {}", i, n, note_changes.join(", ")));
+        }
+    }
+}
+
+/// Changed fields of a note, like `C2→D2, volume 5→3`.
+fn diff_note(a: &Note, b: &Note) -> Vec<String> {
+    let mut fields = vec![];
+    let (pa, pb) = (a.pitch(), b.pitch());
+    if pa != pb {
+        fields.push(format!(
+            "{}{}→{}{}",
+            pa.name(),
+            pa.octave(),
+            pb.name(),
+            pb.octave()
+        ));
+    }
+    let mut field = |name: &str, a: String, b: String| {
+        if a != b {
+            fields.push(format!("{} {}→{}", name, a, b));
+        }
+    };
+    field(
+        "instrument",
+        a.instrument().to_string(),
+        b.instrument().to_string(),
+    );
+    field(
+        "volume",
+        u8::from(a.volume()).to_string(),
+        u8::from(b.volume()).to_string(),
+    );
+    field(
+        "effect",
+        format!("{:?}", a.effect()),
+        format!("{:?}", b.effect()),
+    );
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_map_rows_and_code_lines_are_diffed() {
+        let mut a = Cartridge::from_bytes(vec![0; Cartridge::LEN]).unwrap();
+        a.set_code(b"x=1\ny=2\nz=3\n").unwrap();
+        let mut b = a.clone();
+        b.set_code(b"x=1\ny=\x8e\nz=3\n").unwrap();
+        // Cell (5,40) is in the half of the map shared with sprites 128–255.
+        b.bytes_mut(0x1000 + 8 * 128 + 5..0x1000 + 8 * 128 + 6)
+            .unwrap()[0] = 7;
+
+        let changes = changes(&a, &b, ("a.p8", "b.p8")).unwrap();
+        assert!(changes.contains(&"map cell (5,40): 0→7".to_string()));
+        let code = changes.iter().position(|c| c == "--- a.p8 code").unwrap();
+        assert_eq!(
+            vec![
+                "+++ b.p8 code",
+                "@@ -1,3 +1,3 @@",
+                " x=1",
+                "-y=2",
+                "+y=\u{1f17e}\u{fe0f}",
+                " z=3"
+            ],
+            changes[code + 1..]
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use clap::ValueEnum;
+use packed_struct::PackedStruct;
+use pico8_rom::music::{Section as MusicSection, SongEnd};
+use pico8_rom::rom;
+use pico8_rom::sfx::Section as SfxSection;
+use pico8_rom::{gfx, p8, p8scii, Cartridge};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fmt::{Debug, Write as _};
+use std::io::Write;
+use std::path::Path;
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+pub enum Format {
+    /// Rust debug output.
+    #[default]
+    Debug,
+    Json,
+    Yaml,
+    Ron,
+}
+
+impl Format {
+    /// Guess a format from a file extension.
+    pub fn from_path(path: &Path) -> Option<Format> {
+        match path.extension()?.to_str()? {
+            "json" => Some(Format::Json),
+            "yaml" | "yml" => Some(Format::Yaml),
+            "ron" => Some(Format::Ron),
+            _ => None,
+        }
+    }
+}
+
+/// Read a section previously written by `dump_section`. Debug output can't be read back.
+pub fn parse_section<T: DeserializeOwned>(path: &Path) -> anyhow::Result<T> {
+    let text = std::fs::read_to_string(path)?;
+    let section = match Format::from_path(path) {
+        Some(Format::Json) => serde_json::from_str(&text)?,
+        Some(Format::Yaml) => serde_yaml::from_str(&text)?,
+        Some(Format::Ron) => ron::from_str(&text)?,
+        Some(Format::Debug) | None => anyhow::bail!(
+            "Not a supported data format (expected .json, .yaml, or .ron): {}",
+            path.to_string_lossy()
+        ),
+    };
+    Ok(section)
+}
+
+/// A section as text in `format`, the way `dump` prints it.
+pub fn format_section<T: Serialize + Debug>(section: &T, format: Format) -> anyhow::Result<String> {
+    Ok(match format {
+        Format::Debug => format!("{:#?}\n", section),
+        Format::Json => format!("{}\n", serde_json::to_string_pretty(section)?),
+        Format::Yaml => serde_yaml::to_string(section)?,
+        Format::Ron => format!(
+            "{}\n",
+            ron::ser::to_string_pretty(section, ron::ser::PrettyConfig::default())?
+        ),
+    })
+}
+
+fn dump_section<T: PackedStruct + Serialize + Debug>(
+    path: &Path,
+    offset: u64,
+    format: Format,
+) -> anyhow::Result<()> {
+    let section = rom::read_section::<T>(path, offset)?;
+    print!("{}", format_section(&section, format)?);
+    Ok(())
+}
+
+pub fn music(path: &Path, format: Format) -> anyhow::Result<()> {
+    dump_section::<MusicSection>(path, rom::MUSIC.start as u64, format)
+}
+
+pub fn sfx(path: &Path, format: Format) -> anyhow::Result<()> {
+    dump_section::<SfxSection>(path, rom::SFX.start as u64, format)
+}
+
+/// Print a ROM's Lua code as Unicode, the way PICO-8 writes it in `.p8` files.
+pub fn code(path: &Path) -> anyhow::Result<()> {
+    print!("{}", p8scii::decode(&Cartridge::read(path)?.code()?));
+    Ok(())
+}
+
+/// Print a cart's label with a digit for each pixel, as in `.p8` files, or write it as a PNG image.
+pub fn label(path: &Path, png: bool) -> anyhow::Result<()> {
+    let cartridge = Cartridge::read(path)?;
+    let label = cartridge
+        .label()
+        .ok_or_else(|| anyhow::anyhow!("{} doesn't have a label", path.to_string_lossy()))?;
+    if png {
+        let size = gfx::LABEL_SIZE as u32;
+        std::io::stdout().write_all(&gfx::to_png(size, size, label)?)?;
+        return Ok(());
+    }
+    for row in label.chunks(gfx::LABEL_SIZE) {
+        let digits = row.iter().map(|p| p8::LABEL_DIGITS[*p as usize] as char);
+        println!("{}", digits.collect::<String>());
+    }
+    Ok(())
+}
+
+/// Print the patterns of each song in the order they play,
+/// then any patterns that playback can't reach, then which patterns use each SFX.
+pub fn songs(path: &Path) -> anyhow::Result<()> {
+    let section = rom::read_section::<MusicSection>(path, rom::MUSIC.start as u64)?;
+    let songs = section.songs();
+    for (i, song) in songs.iter().enumerate() {
+        let end = match song.end {
+            SongEnd::Stop => "stops".to_string(),
+            SongEnd::LoopBack(to) => format!("loops back to pattern {}", to),
+            SongEnd::RunOut => "runs out of patterns".to_string(),
+        };
+        println!("Song {} ({})", i, end);
+        for p in song.patterns.iter() {
+            println!("  {}", section.patterns[*p].summary(*p));
+        }
+        println!();
+    }
+
+    let reachable = songs
+        .iter()
+        .flat_map(|song| song.patterns.iter().copied())
+        .collect::<BTreeSet<_>>();
+    let unreachable = (0..section.patterns.len())
+        .filter(|p| section.patterns[*p].enabled() && !reachable.contains(p))
+        .collect::<Vec<_>>();
+    if !unreachable.is_empty() {
+        println!("Unreachable (only music() can start these)");
+        for p in unreachable {
+            println!("  {}", section.patterns[p].summary(p));
+        }
+        println!();
+    }
+
+    println!("SFX used by patterns");
+    for (sfx_id, patterns) in section.sfx_usage() {
+        let patterns = patterns
+            .iter()
+            .map(|p| p.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        println!("  SFX {:02}: {}", sfx_id, patterns);
+    }
+    Ok(())
+}
+
+/// Print SFX the way the PICO-8 tracker shows them, optionally skipping silent ones.
+pub fn tracker(path: &Path, only_used: bool) -> anyhow::Result<()> {
+    let section = rom::read_section::<SfxSection>(path, rom::SFX.start as u64)?;
+    print!("{}", tracker_text(&section, only_used));
+    Ok(())
+}
+
+fn tracker_text(section: &SfxSection, only_used: bool) -> String {
+    let mut text = String::new();
+    for (i, sfx) in section.sfxes.iter().enumerate() {
+        if only_used && !sfx.enabled() {
+            continue;
+        }
+        let filters = sfx.switches.filters();
+        let _ = writeln!(
+            text,
+            "SFX {:02}  speed {}  loop {}–{}  filters: {}",
+            i,
+            sfx.speed,
+            sfx.loop_start,
+            sfx.loop_end,
+            if filters.is_empty() {
+                "none".to_string()
+            } else {
+                filters.join(", ")
+            }
+        );
+        for (row, note) in sfx.notes.iter().enumerate() {
+            let _ = writeln!(text, "{:02} {}", row, note.tracker_row());
+        }
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pico8_rom::packed_struct::prelude::Integer;
+    use pico8_rom::sfx::{Instrument, Note, Pitch};
+
+    #[test]
+    fn only_used_leaves_out_silent_sfx() {
+        let mut section = SfxSection::default();
+        let mut note = Note::default();
+        note.set_pitch(Pitch::try_from(25).unwrap());
+        note.set_instrument(Instrument::Pulse);
+        note.set_volume(Integer::from(5));
+        section.sfxes[2].notes[1] = note;
+        section.sfxes[2].speed = 16;
+        section.sfxes[2].switches.noiz = true;
+
+        let text = tracker_text(&section, true);
+        assert!(text.starts_with(
+            "SFX 02  speed 16  loop 0–0  filters: noiz\n00 ... . . .\n01 C#2 4 5 0\n"
+        ));
+        assert_eq!(1, text.matches("SFX").count());
+        assert_eq!(64, tracker_text(&section, false).matches("SFX").count());
+    }
+}
@@ -0,0 +1,182 @@
+use clap::ValueEnum;
+use pico8_rom::lua::{self, Stats};
+use pico8_rom::p8scii;
+use std::path::Path;
+
+/// Base64 digits that are all ASCII and never need escaping in a PICO-8 string,
+/// in order of value so `ord` arithmetic or a lookup table can decode them.
+const BASE64_DIGITS: &[u8; 64] =
+    b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ_-";
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    /// Two hex digits per byte, high digit first, for `tonum("0x"..sub(s,i,i+1))`.
+    Hex,
+    /// Four digits per three bytes, most significant bits first, without padding.
+    Base64,
+    /// One character per byte, with control characters, quotes, and backslashes escaped, for `ord(s,i)`.
+    Raw,
+}
+
+impl Encoding {
+    /// A string literal holding `bytes`, as P8SCII code.
+    fn literal(self, bytes: &[u8]) -> Vec<u8> {
+        let digits = match self {
+            Encoding::Hex => bytes
+                .iter()
+                .flat_map(|b| format!("{:02x}", b).into_bytes())
+                .collect(),
+            Encoding::Base64 => base64(bytes),
+            Encoding::Raw => return lua::string_literal(bytes),
+        };
+        [&b"\""[..], &digits, b"\""].concat()
+    }
+}
+
+fn base64(bytes: &[u8]) -> Vec<u8> {
+    let mut digits = vec![];
+    for group in bytes.chunks(3) {
+        let bits = group
+            .iter()
+            .chain([0, 0].iter())
+            .take(3)
+            .fold(0, |bits, b| bits << 8 | *b as usize);
+        for i in 0..=group.len() {
+            digits.push(BASE64_DIGITS[bits >> (18 - i * 6) & 0x3f]);
+        }
+    }
+    digits
+}
+
+/// Report what a file costs as a string literal in each encoding,
+/// then print the literal in the chosen encoding, or the one that compresses smallest.
+pub fn encode(path: &Path, encoding: Option<Encoding>) -> anyhow::Result<()> {
+    let literals = literals(&std::fs::read(path)?)?;
+    eprintln!("encoding tokens chars compressed");
+    for (candidate, stats, _) in literals.iter() {
+        eprintln!(
+            "{:8} {:6} {:5} {:10}",
+            format!("{:?}", candidate).to_lowercase(),
+            stats.tokens,
+            stats.chars,
+            stats.compressed
+        );
+    }
+    if let Some((encoding, _, literal)) = choose(&literals, encoding) {
+        eprintln!("Using {}", format!("{:?}", encoding).to_lowercase());
+        println!("{}", p8scii::decode(literal));
+    }
+    Ok(())
+}
+
+/// The literal in each encoding, with what it costs.
+fn literals(bytes: &[u8]) -> anyhow::Result<Vec<(Encoding, Stats, Vec<u8>)>> {
+    Encoding::value_variants()
+        .iter()
+        .map(|encoding| {
+            let literal = encoding.literal(bytes);
+            Ok((*encoding, Stats::of(&literal)?, literal))
+        })
+        .collect()
+}
+
+/// The literal in `encoding`, or the first that compresses smallest.
+fn choose(
+    literals: &[(Encoding, Stats, Vec<u8>)],
+    encoding: Option<Encoding>,
+) -> Option<&(Encoding, Stats, Vec<u8>)> {
+    match encoding {
+        Some(encoding) => literals.iter().find(|(e, ..)| *e == encoding),
+        None => literals.iter().min_by_key(|(_, stats, _)| stats.compressed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pico8_rom::lua::Kind;
+
+    /// Every byte value, with digits after control characters to catch greedy decimal escapes.
+    fn bytes() -> Vec<u8> {
+        (0..=255).chain([1, b'2', b'"', b'\\', 0, b'0']).collect()
+    }
+
+    /// The contents of a literal, checking that it's a single string token.
+    fn contents(literal: &[u8]) -> &[u8] {
+        let tokens = lua::tokenize(literal).unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].kind, Kind::String);
+        &literal[1..literal.len() - 1]
+    }
+
+    #[test]
+    fn hex_decodes_two_digits_at_a_time() {
+        let digits = contents(&Encoding::Hex.literal(&bytes())).to_vec();
+        // tonum("0x"..sub(s,i,i+1))
+        let decoded = digits
+            .chunks(2)
+            .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(decoded, bytes());
+    }
+
+    #[test]
+    fn base64_decodes_four_digits_to_three_bytes() {
+        for len in [0, 1, 2, 3, 100, bytes().len()] {
+            let data = &bytes()[..len];
+            let digits = contents(&Encoding::Base64.literal(data)).to_vec();
+            let mut decoded = vec![];
+            for group in digits.chunks(4) {
+                let bits = group.iter().fold(0, |bits, digit| {
+                    let value = BASE64_DIGITS.iter().position(|d| d == digit).unwrap();
+                    bits << 6 | value
+                }) << (6 * (4 - group.len()));
+                decoded.extend((0..group.len() - 1).map(|i| (bits >> (16 - i * 8)) as u8));
+            }
+            assert_eq!(decoded, data);
+        }
+    }
+
+    #[test]
+    fn raw_decodes_with_lua_escapes() {
+        let literal = Encoding::Raw.literal(&bytes());
+        let mut escaped = contents(&literal).iter().copied().peekable();
+        let mut decoded = vec![];
+        while let Some(c) = escaped.next() {
+            if c != b'\\' {
+                decoded.push(c);
+                continue;
+            }
+            match escaped.next().unwrap() {
+                b'n' => decoded.push(b'\n'),
+                b'r' => decoded.push(b'\r'),
+                b't' => decoded.push(b'\t'),
+                digit @ b'0'..=b'9' => {
+                    // Lua reads up to three decimal digits.
+                    let mut value = (digit - b'0') as u32;
+                    for _ in 0..2 {
+                        match escaped.next_if(u8::is_ascii_digit) {
+                            Some(digit) => value = value * 10 + (digit - b'0') as u32,
+                            None => break,
+                        }
+                    }
+                    decoded.push(value as u8);
+                }
+                c => decoded.push(c),
+            }
+        }
+        assert_eq!(decoded, bytes());
+    }
+
+    #[test]
+    fn the_smallest_compressed_literal_is_chosen_unless_one_is_asked_for() {
+        let chosen = |bytes: &[u8], encoding| {
+            let literals = literals(bytes).unwrap();
+            choose(&literals, encoding).unwrap().0
+        };
+        assert_eq!(chosen(&[0; 1000], None), Encoding::Base64);
+        let text = b"hello world, this is some text that repeats. ".repeat(2);
+        assert_eq!(chosen(&text, None), Encoding::Raw);
+        assert_eq!(chosen(&text, Some(Encoding::Hex)), Encoding::Hex);
+    }
+}
@@ -0,0 +1,60 @@
+use thiserror::Error;
+
+/// Problems decoding or editing PICO-8 cartridge data.
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("PICO-8 pitches must be 0–63 (C0–D♯5); {0} is out of range")]
+    Pitch(u8),
+    #[error("PICO-8 effects must be 0–7; {0} is out of range")]
+    Effect(u8),
+    #[error("PICO-8 waveforms must be 0–7; {0} is out of range")]
+    Waveform(u8),
+    #[error("{filter} must be 0–2; {value} is out of range")]
+    Filter { filter: &'static str, value: u8 },
+    #[error("Detune, reverb, and dampen are packed as a number below 27; {0} can't be decoded")]
+    PackedFilters(u8),
+    #[error("SFX {name} must be 0–32; {value} is out of range")]
+    LoopPoint { name: &'static str, value: u8 },
+    #[error("SFX with notes need a speed of 1–255; at speed 0 they don't play")]
+    Speed,
+    #[error("PICO-8 volumes must be 0–7; {0} is out of range")]
+    Volume(u8),
+    #[error("PICO-8 SFX numbers must be 0–63; {0} is out of range")]
+    SfxId(u8),
+    #[error("Expected {expected} {name}, found {actual}")]
+    Count {
+        name: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("Compressed code is malformed: {0}")]
+    Code(&'static str),
+    #[error("Code is too long for PICO-8 to store: {0} bytes")]
+    CodeTooLong(usize),
+    #[error("Lua syntax error on line {line}: {message}")]
+    Lua { line: usize, message: &'static str },
+    #[error("{0:?} isn't a P8SCII character")]
+    P8scii(char),
+    #[error("Section can't be packed or unpacked: {0}")]
+    Packing(#[from] packed_struct::PackingError),
+    #[error("PICO-8 colors must be 0–15; {0} is out of range")]
+    Color(u8),
+    #[error("Label colors must be 0–31; {0} is out of range")]
+    LabelColor(u8),
+    #[error(".p8 file is malformed on line {line}: {message}")]
+    P8 { line: usize, message: &'static str },
+    #[error("PNG image can't be read or written: {0}")]
+    Png(String),
+    #[error("Not a supported PICO-8 cartridge format (expected .p8.rom, .p8, or .p8.png): {0}")]
+    FileFormat(String),
+    #[error("File can't be read or written: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("JSON can't be written: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Unknown target {0:?}; expected wasm4, tic80, hugedriver, or famitracker")]
+    Target(String),
+    #[error("Output can't be formatted")]
+    Fmt(#[from] std::fmt::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
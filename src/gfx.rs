@@ -0,0 +1,159 @@
+use crate::cartridge::Cartridge;
+use crate::error::{Error, Result};
+use crate::rom::{self, GFX};
+
+/// RGB values of the 16 standard colors.
+/// See https://pico-8.fandom.com/wiki/Palette
+pub const PALETTE: [[u8; 3]; 16] = [
+    [0x00, 0x00, 0x00],
+    [0x1d, 0x2b, 0x53],
+    [0x7e, 0x25, 0x53],
+    [0x00, 0x87, 0x51],
+    [0xab, 0x52, 0x36],
+    [0x5f, 0x57, 0x4f],
+    [0xc2, 0xc3, 0xc7],
+    [0xff, 0xf1, 0xe8],
+    [0xff, 0x00, 0x4d],
+    [0xff, 0xa3, 0x00],
+    [0xff, 0xec, 0x27],
+    [0x00, 0xe4, 0x36],
+    [0x29, 0xad, 0xff],
+    [0x83, 0x76, 0x9c],
+    [0xff, 0x77, 0xa8],
+    [0xff, 0xcc, 0xaa],
+];
+
+/// RGB values of the 16 extra colors, 128–143 on screen, which labels can use as colors 16–31.
+/// See https://pico-8.fandom.com/wiki/Palette#The_Extended_Palette
+pub const EXTRA_PALETTE: [[u8; 3]; 16] = [
+    [0x29, 0x18, 0x14],
+    [0x11, 0x1d, 0x35],
+    [0x42, 0x21, 0x36],
+    [0x12, 0x53, 0x59],
+    [0x74, 0x2f, 0x29],
+    [0x49, 0x33, 0x3b],
+    [0xa2, 0x88, 0x79],
+    [0xf3, 0xef, 0x7d],
+    [0xbe, 0x12, 0x50],
+    [0xff, 0x6c, 0x24],
+    [0xa8, 0xe7, 0x2e],
+    [0x00, 0xb5, 0x43],
+    [0x06, 0x5a, 0xb5],
+    [0x75, 0x46, 0x65],
+    [0xff, 0x6e, 0x59],
+    [0xff, 0x9d, 0x81],
+];
+
+/// Width and height of the sprite sheet in pixels.
+pub const SHEET_SIZE: usize = 128;
+
+/// Width and height of a cart label in pixels.
+pub const LABEL_SIZE: usize = 128;
+
+/// Color of each pixel of the sprite sheet, left to right and top to bottom.
+/// Each byte holds two pixels, with the left one in the low nibble.
+pub fn sprite_sheet(cartridge: &Cartridge) -> Result<Vec<u8>> {
+    Ok(unpack_pixels(cartridge.bytes(GFX)?))
+}
+
+/// Color of each pixel of one 8×8 sprite.
+pub fn sprite(cartridge: &Cartridge, sprite: usize) -> Result<Vec<u8>> {
+    let gfx = cartridge.bytes(GFX)?;
+    Ok(rom::sprite_rows(sprite)
+        .flat_map(|row| unpack_pixels(&gfx[row]))
+        .collect())
+}
+
+/// Replace the sprite sheet with one color per pixel, as returned by `sprite_sheet`.
+pub fn set_sprite_sheet(cartridge: &mut Cartridge, pixels: &[u8]) -> Result<()> {
+    if pixels.len() != SHEET_SIZE * SHEET_SIZE {
+        return Err(Error::Count {
+            name: "pixels",
+            expected: SHEET_SIZE * SHEET_SIZE,
+            actual: pixels.len(),
+        });
+    }
+    if let Some(color) = pixels.iter().find(|c| **c > 15) {
+        return Err(Error::Color(*color));
+    }
+    cartridge
+        .bytes_mut(GFX)?
+        .copy_from_slice(&pack_pixels(pixels));
+    Ok(())
+}
+
+pub fn unpack_pixels(bytes: &[u8]) -> Vec<u8> {
+    bytes.iter().flat_map(|b| [b & 0xf, b >> 4]).collect()
+}
+
+pub fn pack_pixels(pixels: &[u8]) -> Vec<u8> {
+    pixels
+        .chunks(2)
+        .map(|pair| pair[0] & 0xf | pair.get(1).unwrap_or(&0) << 4)
+        .collect()
+}
+
+/// Closest color to an RGB value, from the standard palette or as 16–31 from the extra palette.
+pub fn nearest_color(rgb: [u8; 3]) -> u8 {
+    let distance = |color: &[u8; 3]| {
+        (0..3)
+            .map(|i| (color[i] as i32 - rgb[i] as i32).pow(2))
+            .sum::<i32>()
+    };
+    PALETTE
+        .iter()
+        .chain(EXTRA_PALETTE.iter())
+        .enumerate()
+        .min_by_key(|(_, color)| distance(color))
+        .map(|(i, _)| i as u8)
+        .unwrap_or_default()
+}
+
+/// Width, height, and RGBA value of each pixel of a PNG image in any color format.
+pub fn read_png(bytes: &[u8]) -> Result<(u32, u32, Vec<[u8; 4]>)> {
+    let png_error = |e: png::DecodingError| Error::Png(e.to_string());
+    let mut decoder = png::Decoder::new(std::io::Cursor::new(bytes));
+    decoder.set_transformations(png::Transformations::ALPHA | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(png_error)?;
+    let mut buf = vec![0; reader.output_buffer_size().unwrap_or_default()];
+    let info = reader.next_frame(&mut buf).map_err(png_error)?;
+    let pixels = match info.color_type {
+        png::ColorType::Rgba => buf
+            .chunks_exact(4)
+            .map(|p| [p[0], p[1], p[2], p[3]])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => buf
+            .chunks_exact(2)
+            .map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        other => return Err(Error::Png(format!("{:?} images aren't supported", other))),
+    };
+    Ok((info.width, info.height, pixels))
+}
+
+/// Width, height, and closest PICO-8 color of each pixel of a PNG image.
+pub fn from_png(bytes: &[u8]) -> Result<(u32, u32, Vec<u8>)> {
+    let (width, height, pixels) = read_png(bytes)?;
+    let colors = pixels
+        .iter()
+        .map(|p| nearest_color([p[0], p[1], p[2]]))
+        .collect();
+    Ok((width, height, colors))
+}
+
+/// Indexed-color PNG of pixels using the standard palette, then the extra palette for colors 16–31.
+pub fn to_png(width: u32, height: u32, pixels: &[u8]) -> Result<Vec<u8>> {
+    let mut png = vec![];
+    let mut encoder = png::Encoder::new(&mut png, width, height);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette([PALETTE.concat(), EXTRA_PALETTE.concat()].concat());
+    let mut writer = encoder
+        .write_header()
+        .map_err(|e| Error::Png(e.to_string()))?;
+    writer
+        .write_image_data(pixels)
+        .map_err(|e| Error::Png(e.to_string()))?;
+    writer.finish().map_err(|e| Error::Png(e.to_string()))?;
+    Ok(png)
+}
@@ -0,0 +1,31 @@
+//! Reading and writing the music, SFX, and other data in PICO-8 cartridges.
+//!
+//! Start with [`Cartridge`], which holds a whole ROM and reads and writes its sections.
+//! Section types are [`packed_struct`] structs laid out as in PICO-8 memory,
+//! with accessors for fields that don't line up with bytes.
+//!
+//! Everything public here follows semver.
+//! The `pico8_rom_tool` binary's modules are not part of the library API.
+
+pub mod cartridge;
+pub mod code;
+pub mod error;
+pub mod gfx;
+pub mod lua;
+pub mod music;
+pub mod p8;
+pub mod p8png;
+pub mod p8scii;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod report;
+pub mod rom;
+pub mod sfx;
+pub mod translate;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use cartridge::Cartridge;
+pub use error::{Error, Result};
+/// Re-exported so users can name the packed types in section fields.
+pub use packed_struct;
@@ -0,0 +1,161 @@
+use crate::dump;
+use pico8_rom::cartridge::FileFormat;
+use pico8_rom::music::Section as MusicSection;
+use pico8_rom::rom;
+use pico8_rom::sfx::Section as SfxSection;
+use pico8_rom::{gfx, p8scii, Cartridge};
+use std::path::Path;
+
+/// Replace a ROM's music with patterns read from a JSON, YAML, or RON file.
+/// Out-of-range SFX numbers are rejected while parsing.
+pub fn load_music(input: &Path, path: &Path, output: &Path) -> anyhow::Result<()> {
+    let music = dump::parse_section::<MusicSection>(input)?;
+    Ok(rom::write_section(
+        path,
+        output,
+        rom::MUSIC.start as u64,
+        &music,
+    )?)
+}
+
+/// Replace a ROM's SFX with SFX read from a JSON, YAML, or RON file.
+/// Out-of-range pitches, volumes, and filters are rejected while parsing,
+/// and anything else `validate` would complain about is rejected before writing.
+pub fn load_sfx(input: &Path, path: &Path, output: &Path) -> anyhow::Result<()> {
+    let sfx = dump::parse_section::<SfxSection>(input)?;
+    let errors = sfx.validate();
+    for (i, e) in errors.iter() {
+        eprintln!("SFX {}: {}", i, e);
+    }
+    if !errors.is_empty() {
+        anyhow::bail!(
+            "{} problem(s) found in {}",
+            errors.len(),
+            input.to_string_lossy()
+        );
+    }
+    Ok(rom::write_section(
+        path,
+        output,
+        rom::SFX.start as u64,
+        &sfx,
+    )?)
+}
+
+/// Replace a ROM's code with a Unicode Lua file, like one written by `dump code`.
+pub fn load_code(input: &Path, path: &Path, output: &Path) -> anyhow::Result<()> {
+    let code = p8scii::encode(&std::fs::read_to_string(input)?)?;
+    let mut cartridge = Cartridge::read(path)?;
+    cartridge.set_code(&code)?;
+    Ok(cartridge.write(output)?)
+}
+
+/// Set a cart's label from a 128×128 PNG image, using the closest PICO-8 color for each pixel.
+pub fn load_label(input: &Path, path: &Path, output: &Path) -> anyhow::Result<()> {
+    if FileFormat::from_path(output)? == FileFormat::Rom {
+        anyhow::bail!("ROMs don't have labels; write a .p8 or .p8.png file instead");
+    }
+    let (width, height, pixels) = gfx::from_png(&std::fs::read(input)?)?;
+    let size = gfx::LABEL_SIZE as u32;
+    if (width, height) != (size, size) {
+        anyhow::bail!(
+            "Labels are {}×{}, but {} is {}×{}",
+            size,
+            size,
+            input.to_string_lossy(),
+            width,
+            height
+        );
+    }
+    let mut cartridge = Cartridge::read(path)?;
+    cartridge.set_label(pixels)?;
+    Ok(cartridge.write(output)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dump::{format_section, Format};
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "pico8_rom_tool_load_{}_{}",
+            std::process::id(),
+            name
+        ))
+    }
+
+    /// A ROM with different notes, filters, speeds, loop points, and patterns in every SFX and pattern.
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![0; Cartridge::LEN];
+        for (i, byte) in rom[rom::MUSIC].iter_mut().enumerate() {
+            *byte = (i * 37) as u8;
+        }
+        for (s, sfx) in rom[rom::SFX].chunks_mut(68).enumerate() {
+            for (i, byte) in sfx[..64].iter_mut().enumerate() {
+                *byte = (s * 64 + i * 13) as u8;
+            }
+            sfx[64] = (((s % 27) << 3) | (s % 8)) as u8;
+            sfx[65] = s as u8 + 1;
+            sfx[66] = (s % 33) as u8;
+            sfx[67] = (s * 7 % 33) as u8;
+        }
+        rom
+    }
+
+    #[test]
+    fn dumps_load_back_into_identical_roms() {
+        let path = temp_path("in.p8.rom");
+        let output = temp_path("out.p8.rom");
+        std::fs::write(&path, rom()).unwrap();
+        let cartridge = Cartridge::read(&path).unwrap();
+        for (format, extension) in [
+            (Format::Json, "json"),
+            (Format::Yaml, "yaml"),
+            (Format::Ron, "ron"),
+        ] {
+            let music = temp_path(&format!("music.{}", extension));
+            std::fs::write(
+                &music,
+                format_section(&cartridge.music().unwrap(), format).unwrap(),
+            )
+            .unwrap();
+            load_music(&music, &path, &output).unwrap();
+            assert_eq!(rom(), std::fs::read(&output).unwrap());
+
+            let sfx = temp_path(&format!("sfx.{}", extension));
+            std::fs::write(
+                &sfx,
+                format_section(&cartridge.sfx().unwrap(), format).unwrap(),
+            )
+            .unwrap();
+            load_sfx(&sfx, &path, &output).unwrap();
+            assert_eq!(rom(), std::fs::read(&output).unwrap());
+            std::fs::remove_file(music).unwrap();
+            std::fs::remove_file(sfx).unwrap();
+        }
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(output).unwrap();
+    }
+
+    #[test]
+    fn bad_sfx_are_rejected() {
+        let path = temp_path("bad.p8.rom");
+        let output = temp_path("bad_out.p8.rom");
+        std::fs::write(&path, rom()).unwrap();
+        let section = Cartridge::read(&path).unwrap().sfx().unwrap();
+        let json = format_section(&section, Format::Json).unwrap();
+        for (field, bad) in [
+            ("\"speed\": 1,", "\"speed\": 0,"),
+            ("\"loop_start\": 0,", "\"loop_start\": 40,"),
+        ] {
+            let input = temp_path("bad.json");
+            std::fs::write(&input, json.replacen(field, bad, 1)).unwrap();
+            assert!(load_sfx(&input, &path, &output).is_err(), "{}", bad);
+            assert!(!output.exists());
+            std::fs::remove_file(input).unwrap();
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,593 @@
+use crate::code;
+use crate::error::{Error, Result};
+use crate::p8scii;
+use crate::rom::CODE;
+
+/// See https://www.lua.org/manual/5.2/manual.html#3.1
+const KEYWORDS: &[&[u8]] = &[
+    b"and",
+    b"break",
+    b"do",
+    b"else",
+    b"elseif",
+    b"end",
+    b"false",
+    b"for",
+    b"function",
+    b"goto",
+    b"if",
+    b"in",
+    b"local",
+    b"nil",
+    b"not",
+    b"or",
+    b"repeat",
+    b"return",
+    b"then",
+    b"true",
+    b"until",
+    b"while",
+];
+
+/// Operators and punctuation, longest first.
+/// PICO-8 adds `!=`, `\` integer division, `^^` xor, rotations, compound assignment,
+/// `@`/`%`/`$` peeks, and `?` for print.
+/// See https://www.lexaloffle.com/dl/docs/pico-8_manual.html#Lua_Syntax_Primer
+const PUNCTUATION: &[&[u8]] = &[
+    b">>>=", b"<<>=", b">><=", b"...", b"..=", b">>>", b"<<>", b">><", b">>=", b"<<=", b"^^=",
+    b"..", b"==", b"~=", b"!=", b"<=", b">=", b"+=", b"-=", b"*=", b"/=", b"\\=", b"%=", b"^=",
+    b"|=", b"&=", b"^^", b">>", b"<<", b"::", b"+", b"-", b"*", b"/", b"\\", b"%", b"^", b"#",
+    b"&", b"|", b"~", b"<", b">", b"=", b"(", b")", b"{", b"}", b"[", b"]", b";", b":", b",", b".",
+    b"@", b"$", b"?",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Name,
+    Keyword,
+    Number,
+    String,
+    Punctuation,
+    Comment,
+}
+
+/// A token of P8SCII source, which may span lines if it's a long string or comment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Token<'a> {
+    pub kind: Kind,
+    pub text: &'a [u8],
+    /// Line the token starts on, counting from 1.
+    pub line: usize,
+}
+
+impl Token<'_> {
+    /// Line the token ends on.
+    pub fn end_line(&self) -> usize {
+        self.line + self.text.iter().filter(|c| **c == b'\n').count()
+    }
+
+    fn is(&self, kind: Kind, text: &str) -> bool {
+        self.kind == kind && self.text == text.as_bytes()
+    }
+
+    fn is_punctuation(&self, texts: &[&str]) -> bool {
+        self.kind == Kind::Punctuation && texts.iter().any(|t| self.text == t.as_bytes())
+    }
+
+    fn is_keyword(&self, texts: &[&str]) -> bool {
+        self.kind == Kind::Keyword && texts.iter().any(|t| self.text == t.as_bytes())
+    }
+}
+
+/// Split source into tokens, including comments but not whitespace.
+pub fn tokenize(code: &[u8]) -> Result<Vec<Token<'_>>> {
+    let mut tokens = vec![];
+    let mut i = 0;
+    let mut line = 1;
+    while i < code.len() {
+        let c = code[i];
+        if c == b'\n' {
+            line += 1;
+            i += 1;
+            continue;
+        }
+        if c.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        }
+        let rest = &code[i..];
+        let error = |message| Error::Lua { line, message };
+        let (kind, len) = if rest.starts_with(b"--") || rest.starts_with(b"//") {
+            let long = rest
+                .starts_with(b"--")
+                .then(|| long_bracket(&rest[2..]))
+                .flatten();
+            let len = match long {
+                Some(len) => 2 + len.ok_or_else(|| error("unfinished long comment"))?,
+                None => rest.iter().position(|c| *c == b'\n').unwrap_or(rest.len()),
+            };
+            (Kind::Comment, len)
+        } else if c.is_ascii_digit() || (c == b'.' && rest.get(1).is_some_and(u8::is_ascii_digit)) {
+            let mut len = 1;
+            while let Some(c) = rest.get(len) {
+                let fraction = *c == b'.' && rest.get(len + 1) != Some(&b'.');
+                if !(is_name_char(*c) || fraction) {
+                    break;
+                }
+                len += 1;
+            }
+            (Kind::Number, len)
+        } else if is_name_char(c) {
+            let len = rest
+                .iter()
+                .position(|c| !is_name_char(*c))
+                .unwrap_or(rest.len());
+            match KEYWORDS.contains(&&rest[..len]) {
+                true => (Kind::Keyword, len),
+                false => (Kind::Name, len),
+            }
+        } else if c == b'"' || c == b'\'' {
+            (
+                Kind::String,
+                quoted_string(rest).ok_or_else(|| error("unfinished string"))?,
+            )
+        } else if let Some(len) = long_bracket(rest) {
+            (
+                Kind::String,
+                len.ok_or_else(|| error("unfinished long string"))?,
+            )
+        } else {
+            let punctuation = PUNCTUATION
+                .iter()
+                .find(|p| rest.starts_with(p))
+                .ok_or_else(|| error("unexpected character"))?;
+            (Kind::Punctuation, punctuation.len())
+        };
+        let token = Token {
+            kind,
+            text: &rest[..len],
+            line,
+        };
+        line = token.end_line();
+        tokens.push(token);
+        i += len;
+    }
+    Ok(tokens)
+}
+
+/// Letters, digits, underscores, and P8SCII glyphs can all be part of names.
+fn is_name_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c >= 0x80
+}
+
+/// Length of a quoted string at the start of `code`, including the quotes.
+fn quoted_string(code: &[u8]) -> Option<usize> {
+    let quote = code[0];
+    let mut i = 1;
+    loop {
+        match *code.get(i)? {
+            b'\\' => i += 2,
+            b'\n' => return None,
+            c if c == quote => return Some(i + 1),
+            _ => i += 1,
+        }
+    }
+}
+
+/// Length of a `[[…]]` or `[==[…]==]` string at the start of `code`,
+/// `Some(None)` if it isn't closed, or `None` if `code` doesn't start with one.
+fn long_bracket(code: &[u8]) -> Option<Option<usize>> {
+    let level = code.strip_prefix(b"[")?.iter().position(|c| *c != b'=')?;
+    if code.get(level + 1) != Some(&b'[') {
+        return None;
+    }
+    let mut close = vec![b']'];
+    close.extend(std::iter::repeat_n(b'=', level));
+    close.push(b']');
+    let start = level + 2;
+    Some(
+        code[start..]
+            .windows(close.len())
+            .position(|w| w == close.as_slice())
+            .map(|end| start + end + close.len()),
+    )
+}
+
+/// Tokens that count against PICO-8's limit of 8192. Commas, periods, colons, semicolons,
+/// closing brackets, `end`, and `local` are free, as is a `-` or `~` in front of a number.
+/// See https://pico-8.fandom.com/wiki/Tokens
+pub fn count_tokens(tokens: &[Token]) -> usize {
+    let tokens = tokens
+        .iter()
+        .filter(|t| t.kind != Kind::Comment)
+        .collect::<Vec<_>>();
+    (0..tokens.len())
+        .filter(|i| {
+            let token = tokens[*i];
+            let free = token.is_punctuation(&[",", ".", ":", ";", "::", ")", "]", "}"])
+                || token.is_keyword(&["end", "local"])
+                || (token.is_punctuation(&["-", "~"])
+                    && tokens.get(i + 1).is_some_and(|t| t.kind == Kind::Number)
+                    && is_unary(i.checked_sub(1).map(|p| tokens[p])));
+            !free
+        })
+        .count()
+}
+
+/// Whether an operator after `prev` is unary, like the `-` in `x = -y` but not `x - y`.
+fn is_unary(prev: Option<&Token>) -> bool {
+    match prev {
+        None => true,
+        Some(prev) => match prev.kind {
+            Kind::Punctuation => !prev.is_punctuation(&[")", "]", "}", "..."]),
+            Kind::Keyword => !prev.is_keyword(&["end", "true", "false", "nil"]),
+            _ => false,
+        },
+    }
+}
+
+/// How a keyword changes block nesting. `elseif` closes a block so that its `then` can open the next,
+/// and shorthand `if (…) …` and `while (…) …` have no `then` or `do`, so they don't open one.
+fn nesting(token: &Token) -> isize {
+    if token.is_keyword(&["function", "do", "then", "repeat"]) {
+        1
+    } else if token.is_keyword(&["end", "until", "elseif"]) {
+        -1
+    } else {
+        0
+    }
+}
+
+/// A function definition and its token count, which includes any functions defined inside it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Function {
+    pub name: String,
+    pub line: usize,
+    pub tokens: usize,
+}
+
+/// Every function definition, in source order.
+pub fn functions(tokens: &[Token]) -> Vec<Function> {
+    let tokens = tokens
+        .iter()
+        .filter(|t| t.kind != Kind::Comment)
+        .copied()
+        .collect::<Vec<_>>();
+    let mut functions = vec![];
+    for (i, token) in tokens.iter().enumerate() {
+        if !token.is(Kind::Keyword, "function") {
+            continue;
+        }
+        let mut depth = 0;
+        let end = (i..tokens.len())
+            .find(|j| {
+                depth += nesting(&tokens[*j]);
+                depth == 0
+            })
+            .unwrap_or(tokens.len() - 1);
+        functions.push(Function {
+            name: function_name(&tokens, i),
+            line: token.line,
+            tokens: count_tokens(&tokens[i..=end]),
+        });
+    }
+    functions
+}
+
+/// `a.b:c` for `function a.b:c()`, `a.b` for `a.b = function()`, or `(anonymous)`.
+fn function_name(tokens: &[Token], i: usize) -> String {
+    let is_path = |t: &&Token| t.kind == Kind::Name || t.is_punctuation(&[".", ":"]);
+    let mut path = tokens[i + 1..]
+        .iter()
+        .take_while(is_path)
+        .collect::<Vec<_>>();
+    if path.is_empty() && i >= 2 && tokens[i - 1].is(Kind::Punctuation, "=") {
+        path = tokens[..i - 1].iter().rev().take_while(is_path).collect();
+        path.reverse();
+    }
+    match path.is_empty() {
+        true => "(anonymous)".to_string(),
+        false => path.iter().map(|t| p8scii::decode(t.text)).collect(),
+    }
+}
+
+/// The cart's title and author: the first two lines of code, if they're `--` comments.
+pub fn title(code: &[u8]) -> Vec<String> {
+    code.split(|c| *c == b'\n')
+        .take(2)
+        .map_while(|line| line.strip_prefix(b"--"))
+        .map(|line| p8scii::decode(line).trim().to_string())
+        .collect()
+}
+
+/// A quoted string literal holding any bytes, as P8SCII code.
+/// Control characters, quotes, and backslashes are escaped; everything else is kept as is.
+pub fn string_literal(bytes: &[u8]) -> Vec<u8> {
+    let mut literal = vec![b'"'];
+    for (i, byte) in bytes.iter().enumerate() {
+        match byte {
+            b'"' | b'\\' => literal.extend([b'\\', *byte]),
+            b'\n' => literal.extend(b"\\n"),
+            b'\r' => literal.extend(b"\\r"),
+            b'\t' => literal.extend(b"\\t"),
+            0..=31 => {
+                // A decimal escape is padded if a digit follows, so it doesn't swallow the digit.
+                let padded = bytes.get(i + 1).is_some_and(u8::is_ascii_digit);
+                let escape = match padded {
+                    true => format!("\\{:03}", byte),
+                    false => format!("\\{}", byte),
+                };
+                literal.extend(escape.bytes());
+            }
+            _ => literal.push(*byte),
+        }
+    }
+    literal.push(b'"');
+    literal
+}
+
+/// Sizes that PICO-8 limits: tokens, characters, and bytes once compressed into a ROM.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stats {
+    pub tokens: usize,
+    pub chars: usize,
+    pub compressed: usize,
+}
+
+impl Stats {
+    pub const MAX_TOKENS: usize = 8192;
+    pub const MAX_CHARS: usize = 65535;
+    pub const MAX_COMPRESSED: usize = CODE.end - CODE.start;
+
+    pub fn of(code: &[u8]) -> Result<Stats> {
+        Ok(Stats {
+            tokens: count_tokens(&tokenize(code)?),
+            chars: code.len(),
+            compressed: code::compress(code)?.len(),
+        })
+    }
+}
+
+/// Remove comments and every space and line break that PICO-8 doesn't need.
+/// Line breaks are kept after shorthand `if (…) …` and `while (…) …` and around `?` print,
+/// which end at the end of the line.
+pub fn minify(code: &[u8]) -> Result<Vec<u8>> {
+    let tokens = tokenize(code)?
+        .into_iter()
+        .filter(|t| t.kind != Kind::Comment)
+        .collect::<Vec<_>>();
+    let (break_before, break_after) = line_sensitive(&tokens);
+    let mut out = vec![];
+    for (i, token) in tokens.iter().enumerate() {
+        if let Some(prev) = i.checked_sub(1).map(|p| &tokens[p]) {
+            let newline = prev.end_line() < token.line;
+            if newline
+                && (break_after.contains(&prev.end_line()) || break_before.contains(&token.line))
+            {
+                out.push(b'\n');
+            } else if needs_space(prev, token) {
+                out.push(if newline { b'\n' } else { b' ' });
+            }
+        }
+        out.extend(token.text);
+    }
+    Ok(out)
+}
+
+/// Lines that need a line break before them and after them.
+fn line_sensitive(tokens: &[Token]) -> (Vec<usize>, Vec<usize>) {
+    let (mut before, mut after) = (vec![], vec![]);
+    for (i, token) in tokens.iter().enumerate() {
+        if token.is_punctuation(&["?"]) {
+            before.push(token.line);
+            after.push(token.line);
+        }
+        let opens = match token.kind {
+            Kind::Keyword if token.text == b"if" => "then",
+            Kind::Keyword if token.text == b"while" => "do",
+            _ => continue,
+        };
+        let shorthand = !tokens[i + 1..]
+            .iter()
+            .take_while(|t| t.line == token.line)
+            .any(|t| t.is(Kind::Keyword, opens));
+        if shorthand {
+            after.push(token.line);
+        }
+    }
+    (before, after)
+}
+
+/// Whether two tokens would run together without a space between them,
+/// like two names, or `-` and `-` becoming a comment.
+fn needs_space(a: &Token, b: &Token) -> bool {
+    let joined = [a.text, b.text].concat();
+    match tokenize(&joined) {
+        Ok(tokens) => {
+            tokens.len() != 2
+                || (
+                    tokens[0].kind,
+                    tokens[0].text,
+                    tokens[1].kind,
+                    tokens[1].text,
+                ) != (a.kind, a.text, b.kind, b.text)
+        }
+        Err(_) => true,
+    }
+}
+
+/// Indent blocks by one space, as PICO-8's editor does, and space out operators.
+/// Line breaks and comments are kept, with runs of blank lines collapsed into one.
+pub fn format(code: &[u8]) -> Result<Vec<u8>> {
+    let tokens = tokenize(code)?;
+    let mut lines: Vec<&[Token]> = vec![];
+    let mut start = 0;
+    for i in 1..=tokens.len() {
+        if i == tokens.len() || tokens[i - 1].end_line() < tokens[i].line {
+            lines.push(&tokens[start..i]);
+            start = i;
+        }
+    }
+
+    let mut out = vec![];
+    let mut depth = 0usize;
+    for (l, line) in lines.iter().enumerate() {
+        if l > 0 {
+            out.push(b'\n');
+            if lines[l - 1].last().map(Token::end_line).unwrap_or_default() + 1 < line[0].line {
+                out.push(b'\n');
+            }
+        }
+        let closers = line
+            .iter()
+            .take_while(|t| {
+                t.is_keyword(&["end", "until", "else", "elseif"]) || t.is(Kind::Punctuation, "}")
+            })
+            .count();
+        out.extend(std::iter::repeat_n(b' ', depth.saturating_sub(closers)));
+        for (i, token) in line.iter().enumerate() {
+            if i > 0 && spaced(i.checked_sub(2).map(|p| &line[p]), &line[i - 1], token) {
+                out.push(b' ');
+            }
+            out.extend(token.text);
+            let braces = match token.kind {
+                Kind::Punctuation if token.text == b"{" => 1,
+                Kind::Punctuation if token.text == b"}" => -1,
+                _ => 0,
+            };
+            depth = depth.saturating_add_signed(nesting(token) + braces);
+        }
+    }
+    if code.ends_with(b"\n") {
+        out.push(b'\n');
+    }
+    Ok(out)
+}
+
+/// Whether the formatter puts a space between `a` and `b`.
+fn spaced(prev: Option<&Token>, a: &Token, b: &Token) -> bool {
+    let spaced = if b.kind == Kind::Comment || a.is_punctuation(&[",", ";"]) {
+        true
+    } else if a.is_punctuation(&["(", "[", "{", ".", ":"])
+        || b.is_punctuation(&[")", "]", "}", ",", ";", ".", ":"])
+    {
+        false
+    } else if b.is_punctuation(&["(", "["]) {
+        !(matches!(a.kind, Kind::Name | Kind::String)
+            || a.is_punctuation(&[")", "]"])
+            || a.is(Kind::Keyword, "function"))
+    } else if a.is_punctuation(&["-", "~", "#", "@", "%", "$"]) {
+        !is_unary(prev)
+    } else {
+        true
+    };
+    spaced || needs_space(a, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(code: &[u8]) -> Vec<String> {
+        tokenize(code)
+            .unwrap()
+            .iter()
+            .map(|t| String::from_utf8_lossy(t.text).to_string())
+            .collect()
+    }
+
+    #[test]
+    fn pico8_syntax_tokenizes() {
+        assert_eq!(
+            vec!["if", "(", "x", "!=", "0", ")", "y", "\\=", "2", "// note"],
+            texts(b"if (x!=0) y\\=2 // note")
+        );
+        assert_eq!(
+            vec![
+                "?",
+                "@",
+                "0x5f00",
+                "..",
+                "%",
+                "0x1.8",
+                "^^",
+                "$",
+                "a",
+                "--[[ x\n]]",
+                "[=[]]=]"
+            ],
+            texts(b"?@0x5f00..%0x1.8^^$a--[[ x\n]][=[]]=]")
+        );
+        let glyphs = tokenize(b"\"\\\"\x8e\" \x8e_1").unwrap();
+        assert_eq!(
+            vec![
+                (Kind::String, &b"\"\\\"\x8e\""[..]),
+                (Kind::Name, b"\x8e_1")
+            ],
+            glyphs.iter().map(|t| (t.kind, t.text)).collect::<Vec<_>>()
+        );
+        assert!(tokenize(b"x = \"oops\ny = 1").is_err());
+    }
+
+    #[test]
+    fn tokens_count_like_pico8() {
+        let count = |code: &[u8]| count_tokens(&tokenize(code).unwrap());
+        assert_eq!(3, count(b"local x = -1 -- comment"));
+        assert_eq!(5, count(b"x = y - 1"));
+        assert_eq!(8, count(b"function f(a, b) return a.b end"));
+        assert_eq!(5, count(b"t = {1, 2}"));
+    }
+
+    #[test]
+    fn functions_are_named_and_counted() {
+        let code = b"function a.b:c() if (x) y() end\nlocal f = function() while x do end end\ng(function() end)";
+        let summary = functions(&tokenize(code).unwrap())
+            .into_iter()
+            .map(|f| (f.name, f.line, f.tokens))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("a.b:c".to_string(), 1, 10),
+                ("f".to_string(), 2, 5),
+                ("(anonymous)".to_string(), 3, 2),
+            ],
+            summary
+        );
+    }
+
+    #[test]
+    fn title_comes_from_leading_comments() {
+        assert_eq!(
+            vec!["\u{2665} love", "by me"],
+            title(b"-- \x87 love\n--by me\n-- not this")
+        );
+        assert_eq!(vec!["title"], title(b"-- title\nx = 1\n-- nope"));
+        assert!(title(b"x = 1\n-- nope").is_empty());
+    }
+
+    #[test]
+    fn string_literals_escape_only_what_they_must() {
+        assert_eq!(
+            b"\"\\\"\\\\\\n\\0001\\31\x8e\"".to_vec(),
+            string_literal(b"\"\\\n\x001\x1f\x8e")
+        );
+        let literal = string_literal(&[0, 7, b'"']);
+        assert_eq!(1, tokenize(&literal).unwrap().len());
+    }
+
+    #[test]
+    fn minified_code_keeps_line_sensitive_statements() {
+        let code = b"-- game\nif (btn(0)) x -= 1\n? \"hi\"\nfor i = 1, 10 do\n  y = y - -i\nend\n";
+        assert_eq!(
+            b"if(btn(0))x-=1\n?\"hi\"\nfor i=1,10 do\ny=y- -i\nend".to_vec(),
+            minify(code).unwrap()
+        );
+    }
+
+    #[test]
+    fn formatted_code_is_indented() {
+        let code = b"function _draw()\ncls()\nif x>0 then\nspr(1,x,-y)\nelse\nprint(\"none\") -- why\nend\n\n\n\nend";
+        let expected = "function _draw()\n cls()\n if x > 0 then\n  spr(1, x, -y)\n else\n  print(\"none\") -- why\n end\n\nend";
+        assert_eq!(expected, String::from_utf8(format(code).unwrap()).unwrap());
+    }
+}
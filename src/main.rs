@@ -1,11 +1,21 @@
-mod music;
-mod rom;
-mod sfx;
-mod translate;
+mod analyze;
+mod diff;
+mod dump;
+mod encode;
+mod load;
+mod merge;
+mod pack;
+mod peek;
+mod source;
+mod validate;
 
-use anyhow;
 use clap::{Parser, Subcommand, ValueEnum};
-use std::path::PathBuf;
+use pico8_rom::lua;
+use pico8_rom::report::Report;
+use pico8_rom::translate;
+use pico8_rom::Cartridge;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 /// Tool for working with resources in PICO-8 ROMs.
 #[derive(Parser, Debug)]
@@ -23,11 +33,141 @@ enum Commands {
         section: Section,
         #[clap(value_parser)]
         path: PathBuf,
+        #[clap(long, value_enum, default_value_t)]
+        format: dump::Format,
+        #[clap(long, value_enum, default_value_t)]
+        view: View,
+        /// Leave out SFX that don't play anything.
+        #[clap(long)]
+        only_used: bool,
+        /// Write the label as a PNG image instead of text.
+        #[clap(long)]
+        png: bool,
     },
-    /// Translate PICO-8 music and sfx to WASM-4 code and data.
+    /// List SFX, sprites, and music patterns that aren't used by music, the map, or code.
+    Analyze {
+        #[clap(value_parser)]
+        path: PathBuf,
+    },
+    /// Measure, minify, or reformat the Lua code in a PICO-8 ROM.
+    Code {
+        #[clap(subcommand)]
+        command: CodeCommand,
+    },
+    /// Compare two PICO-8 carts and list changes to SFX, music, sprites, and the map, then diff the code.
+    Diff {
+        #[clap(value_parser)]
+        a: PathBuf,
+        #[clap(value_parser)]
+        b: PathBuf,
+    },
+    /// Replace a section of a PICO-8 cartridge with data from a JSON, YAML, or RON file written by `dump`,
+    /// a Lua file for code, or a PNG image for the label.
+    Load {
+        #[clap(value_enum)]
+        section: Section,
+        #[clap(value_parser)]
+        input: PathBuf,
+        #[clap(value_parser)]
+        path: PathBuf,
+        /// Where to write the modified cartridge, as `.p8.rom`, `.p8`, or `.p8.png`.
+        #[clap(short, long, value_parser)]
+        output: PathBuf,
+    },
+    /// Three-way merge PICO-8 carts as a git merge driver: `merge-driver %O %A %B %P`.
+    MergeDriver {
+        #[clap(value_parser)]
+        base: PathBuf,
+        #[clap(value_parser)]
+        ours: PathBuf,
+        #[clap(value_parser)]
+        theirs: PathBuf,
+        /// The cart's path in the repository, whose extension gives the format. Without it, carts are ROMs.
+        #[clap(value_parser)]
+        path: Option<PathBuf>,
+    },
+    /// Print a binary file as a PICO-8 string literal,
+    /// after reporting its token, character, and compressed cost in each encoding.
+    Encode {
+        #[clap(value_parser)]
+        path: PathBuf,
+        /// Encoding to print. Defaults to the one that compresses smallest.
+        #[clap(long, value_enum)]
+        encoding: Option<encode::Encoding>,
+    },
+    /// Lay binary files into the data sections of one or more carts for loading with `reload`,
+    /// and print Lua constants with the cart, address, and length of each.
+    Pack {
+        #[clap(value_parser, required = true)]
+        blobs: Vec<PathBuf>,
+        /// First data cart, like `data.p8`. More carts are numbered `data2.p8`, `data3.p8`, and so on.
+        #[clap(short, long, value_parser)]
+        output: PathBuf,
+        /// Write a JSON manifest of where each file went.
+        #[clap(long, value_parser)]
+        manifest: Option<PathBuf>,
+    },
+    /// Print a range of bytes from a PICO-8 cartridge, by address and length or by region name:
+    /// gfx, gfx2, map, flags, music, sfx, or code.
+    Peek {
+        #[clap(value_parser)]
+        path: PathBuf,
+        /// Address, in decimal or `0x` hex, or a region name.
+        #[clap(value_parser)]
+        start: String,
+        #[clap(value_parser = peek::parse_number)]
+        len: Option<usize>,
+        /// Print rows of hex bytes. This is the default.
+        #[clap(long, group = "encoding")]
+        hex: bool,
+        /// Write the bytes as they are.
+        #[clap(long, group = "encoding")]
+        bin: bool,
+        /// Print a Lua string literal of the bytes, for `poke`ing into another cart.
+        #[clap(long, group = "encoding")]
+        lua_string: bool,
+    },
+    /// Check a PICO-8 ROM for data that can't be decoded correctly.
+    Validate {
+        #[clap(value_parser)]
+        path: PathBuf,
+    },
+    /// Translate PICO-8 music and sfx to code and data for another fantasy console.
     Translate {
         #[clap(value_parser)]
         path: PathBuf,
+        /// One of wasm4, tic80, hugedriver (GBDK, for the Game Boy), or famitracker (text export, for the NES).
+        #[clap(long, value_parser = translate::Target::from_str, default_value = "wasm4")]
+        target: translate::Target,
+        /// Write a JSON report of translated, approximated, and rejected SFX and patterns.
+        #[clap(long, value_parser)]
+        report: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum CodeCommand {
+    /// Count tokens, characters, and compressed bytes, overall and per function.
+    /// Fails if the code is over any of PICO-8's limits.
+    Stats {
+        #[clap(value_parser)]
+        path: PathBuf,
+    },
+    /// Remove comments and every space and line break that isn't needed.
+    Minify {
+        #[clap(value_parser)]
+        path: PathBuf,
+        /// Write a ROM with the new code instead of printing the code.
+        #[clap(short, long, value_parser)]
+        output: Option<PathBuf>,
+    },
+    /// Indent blocks and space out operators, keeping comments and line breaks.
+    Format {
+        #[clap(value_parser)]
+        path: PathBuf,
+        /// Write a ROM with the new code instead of printing the code.
+        #[clap(short, long, value_parser)]
+        output: Option<PathBuf>,
     },
 }
 
@@ -35,20 +175,162 @@ enum Commands {
 enum Section {
     Music,
     Sfx,
+    /// Lua source, as Unicode text like in a `.p8` file.
+    Code,
+    /// 128×128 label image, from a `.p8` or `.p8.png` file.
+    Label,
+}
+
+#[derive(ValueEnum, Clone, Debug, Default)]
+enum View {
+    /// Every field of the section, in the chosen format.
+    #[default]
+    Raw,
+    /// SFX rows as shown in the PICO-8 tracker.
+    Tracker,
+    /// Music patterns grouped into songs, with unreachable patterns and SFX usage.
+    Songs,
 }
 
 fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
     match cli.command {
+        Commands::Dump {
+            section: Section::Label,
+            path,
+            view: View::Raw,
+            png,
+            ..
+        } => dump::label(path.as_path(), png)?,
+        Commands::Dump {
+            section, png: true, ..
+        } => anyhow::bail!("PNG output isn't available for {:?}", section),
+        Commands::Dump {
+            section: Section::Music,
+            path,
+            format,
+            view: View::Raw,
+            ..
+        } => dump::music(path.as_path(), format)?,
+        Commands::Dump {
+            section: Section::Sfx,
+            path,
+            format,
+            view: View::Raw,
+            ..
+        } => dump::sfx(path.as_path(), format)?,
+        Commands::Dump {
+            section: Section::Sfx,
+            path,
+            view: View::Tracker,
+            only_used,
+            ..
+        } => dump::tracker(path.as_path(), only_used)?,
         Commands::Dump {
             section: Section::Music,
             path,
-        } => music::dump(path.as_path())?,
+            view: View::Songs,
+            ..
+        } => dump::songs(path.as_path())?,
         Commands::Dump {
+            section: Section::Code,
+            path,
+            view: View::Raw,
+            ..
+        } => dump::code(path.as_path())?,
+        Commands::Dump { section, view, .. } => {
+            anyhow::bail!("The {:?} view isn't available for {:?}", view, section)
+        }
+        Commands::Analyze { path } => analyze::analyze(path.as_path())?,
+        Commands::Code {
+            command: CodeCommand::Stats { path },
+        } => source::stats(path.as_path())?,
+        Commands::Code {
+            command: CodeCommand::Minify { path, output },
+        } => source::rewrite(path.as_path(), output.as_deref(), lua::minify)?,
+        Commands::Code {
+            command: CodeCommand::Format { path, output },
+        } => source::rewrite(path.as_path(), output.as_deref(), lua::format)?,
+        Commands::Diff { a, b } => diff::diff(a.as_path(), b.as_path())?,
+        Commands::Load {
+            section: Section::Music,
+            input,
+            path,
+            output,
+        } => load::load_music(input.as_path(), path.as_path(), output.as_path())?,
+        Commands::Load {
             section: Section::Sfx,
+            input,
+            path,
+            output,
+        } => load::load_sfx(input.as_path(), path.as_path(), output.as_path())?,
+        Commands::Load {
+            section: Section::Code,
+            input,
+            path,
+            output,
+        } => load::load_code(input.as_path(), path.as_path(), output.as_path())?,
+        Commands::Load {
+            section: Section::Label,
+            input,
             path,
-        } => sfx::dump(path.as_path())?,
-        Commands::Translate { path } => translate::translate(path.as_path())?,
+            output,
+        } => load::load_label(input.as_path(), path.as_path(), output.as_path())?,
+        Commands::MergeDriver {
+            base,
+            ours,
+            theirs,
+            path,
+        } => merge::merge(
+            base.as_path(),
+            ours.as_path(),
+            theirs.as_path(),
+            path.as_deref(),
+        )?,
+        Commands::Encode { path, encoding } => encode::encode(path.as_path(), encoding)?,
+        Commands::Pack {
+            blobs,
+            output,
+            manifest,
+        } => pack::pack(&blobs, output.as_path(), manifest.as_deref())?,
+        Commands::Peek {
+            path,
+            start,
+            len,
+            bin,
+            lua_string,
+            ..
+        } => {
+            let encoding = match (bin, lua_string) {
+                (true, _) => peek::Encoding::Bin,
+                (_, true) => peek::Encoding::LuaString,
+                _ => peek::Encoding::Hex,
+            };
+            peek::peek(path.as_path(), &start, len, encoding)?
+        }
+        Commands::Validate { path } => validate::validate(path.as_path())?,
+        Commands::Translate {
+            path,
+            target,
+            report,
+        } => translate_rom(path.as_path(), target, report.as_deref())?,
+    }
+    Ok(())
+}
+
+fn translate_rom(
+    path: &Path,
+    target: translate::Target,
+    report_path: Option<&Path>,
+) -> anyhow::Result<()> {
+    let mut report = Report::default();
+    let translated = translate::translate(&Cartridge::read(path)?, target, &mut report)?;
+    for message in report.messages() {
+        eprintln!("{}", message);
+    }
+    print!("{}", translated);
+    if let Some(report_path) = report_path {
+        report.write(report_path)?;
     }
     Ok(())
 }
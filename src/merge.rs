@@ -0,0 +1,215 @@
+use pico8_rom::cartridge::FileFormat;
+use pico8_rom::rom::{self, GFF, GFX, MAP, MUSIC, SFX};
+use pico8_rom::Cartridge;
+use std::ops::Range;
+use std::path::Path;
+
+/// Something in a ROM that changes as a whole, made of one or more byte ranges.
+struct Unit {
+    name: String,
+    ranges: Vec<Range<usize>>,
+}
+
+impl Unit {
+    fn new(name: String, range: Range<usize>) -> Unit {
+        Unit {
+            name,
+            ranges: vec![range],
+        }
+    }
+
+    fn bytes(&self, rom: &[u8]) -> Vec<u8> {
+        self.ranges
+            .iter()
+            .flat_map(|range| rom[range.clone()].iter().copied())
+            .collect()
+    }
+}
+
+/// Every SFX, pattern, sprite, sprite's flags, and map cell in a ROM.
+fn units() -> Vec<Unit> {
+    let mut units = vec![];
+    for sprite in 0..256 {
+        units.push(Unit {
+            name: format!("sprite {}", sprite),
+            ranges: rom::sprite_rows(sprite)
+                .map(|row| GFX.start + row.start..GFX.start + row.end)
+                .collect(),
+        });
+    }
+    for (i, offset) in MAP.enumerate() {
+        units.push(Unit::new(
+            format!("map cell ({},{})", i % 128, i / 128),
+            offset..offset + 1,
+        ));
+    }
+    for (sprite, offset) in GFF.enumerate() {
+        units.push(Unit::new(
+            format!("sprite {} flags", sprite),
+            offset..offset + 1,
+        ));
+    }
+    for (i, offset) in MUSIC.step_by(4).enumerate() {
+        units.push(Unit::new(format!("pattern {}", i), offset..offset + 4));
+    }
+    for (i, offset) in SFX.step_by(68).enumerate() {
+        units.push(Unit::new(format!("SFX {}", i), offset..offset + 68));
+    }
+    units
+}
+
+/// Three-way merge of carts for use as a git merge driver, invoked as `merge-driver %O %A %B %P`.
+/// Each unit takes whichever side changed it, and the code is merged line by line.
+/// The merged cart is written over `ours`, and anything changed differently on both sides
+/// keeps our version and is reported as a conflict.
+/// See https://git-scm.com/docs/gitattributes#_defining_a_custom_merge_driver
+pub fn merge(base: &Path, ours: &Path, theirs: &Path, path: Option<&Path>) -> anyhow::Result<()> {
+    // Git passes temporary files without an extension, so the format comes from `%P`.
+    let format = match path {
+        Some(path) => FileFormat::from_path(path)?,
+        None => FileFormat::Rom,
+    };
+    let base_cart = Cartridge::read_as(base, format)?;
+    let our_cart = Cartridge::read_as(ours, format)?;
+    let their_cart = Cartridge::read_as(theirs, format)?;
+
+    let (merged, conflicts) = merge_carts(&base_cart, &our_cart, &their_cart)?;
+    merged.write_as(ours, format)?;
+    for conflict in conflicts.iter() {
+        eprintln!("Conflict: {} changed on both sides", conflict);
+    }
+    if !conflicts.is_empty() {
+        anyhow::bail!("{} conflict(s); kept our side of each", conflicts.len());
+    }
+    Ok(())
+}
+
+/// The merged cart, and the names of everything that conflicted.
+fn merge_carts(
+    base: &Cartridge,
+    ours: &Cartridge,
+    theirs: &Cartridge,
+) -> anyhow::Result<(Cartridge, Vec<String>)> {
+    let (base_rom, their_rom) = (base.as_bytes(), theirs.as_bytes());
+    let mut merged = ours.clone();
+    let mut conflicts = vec![];
+    for unit in units() {
+        let (o, a, b) = (
+            unit.bytes(base_rom),
+            unit.bytes(ours.as_bytes()),
+            unit.bytes(their_rom),
+        );
+        if a == b || b == o {
+            continue;
+        }
+        if a != o {
+            conflicts.push(unit.name);
+            continue;
+        }
+        for range in unit.ranges {
+            merged
+                .bytes_mut(range.clone())?
+                .copy_from_slice(&their_rom[range]);
+        }
+    }
+
+    let our_code = ours.code()?;
+    match diffy::merge_bytes(&base.code()?, &our_code, &theirs.code()?) {
+        // Leave our compressed code as it is unless their changes came in.
+        Ok(code) if code != our_code => merged.set_code(&code)?,
+        Ok(_) => {}
+        Err(_) => conflicts.push("code".to_string()),
+    }
+
+    // Adding, changing, and removing the label are all changes.
+    if ours.label() == base.label() && theirs.label() != base.label() {
+        match theirs.label() {
+            Some(label) => merged.set_label(label.to_vec())?,
+            None => merged.remove_label(),
+        }
+    } else if ours.label() != theirs.label() && theirs.label() != base.label() {
+        conflicts.push("label".to_string());
+    }
+    Ok((merged, conflicts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "pico8_rom_tool_merge_{}_{}",
+            std::process::id(),
+            name
+        ))
+    }
+
+    fn cart(code: &[u8]) -> Cartridge {
+        let mut cart = Cartridge::from_bytes(vec![0; Cartridge::LEN]).unwrap();
+        cart.set_code(code).unwrap();
+        cart
+    }
+
+    #[test]
+    fn p8_carts_merge_code_line_by_line() {
+        let base = cart(b"a=1\nb=2\nc=3\n");
+        let mut ours = cart(b"a=10\nb=2\nc=3\n");
+        ours.bytes_mut(SFX.start..SFX.start + 1).unwrap()[0] = 1;
+        let mut theirs = cart(b"a=1\nb=2\nc=30\n");
+        theirs.bytes_mut(GFX.start..GFX.start + 1).unwrap()[0] = 2;
+
+        // Git's temporary files have no extension.
+        let paths = ["base", "ours", "theirs"].map(temp_path);
+        for (cart, path) in [&base, &ours, &theirs].into_iter().zip(paths.iter()) {
+            cart.write_as(path, FileFormat::P8).unwrap();
+        }
+        merge(
+            &paths[0],
+            &paths[1],
+            &paths[2],
+            Some(Path::new("carts/game.p8")),
+        )
+        .unwrap();
+        let merged = Cartridge::read_as(&paths[1], FileFormat::P8).unwrap();
+        for path in paths.iter() {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        assert_eq!(merged.code().unwrap(), b"a=10\nb=2\nc=30\n");
+        assert_eq!(merged.as_bytes()[SFX.start], 1);
+        assert_eq!(merged.as_bytes()[GFX.start], 2);
+    }
+
+    #[test]
+    fn labels_are_added_changed_or_removed_from_either_side() {
+        let mut labelled = cart(b"");
+        labelled.set_label(vec![7; 128 * 128]).unwrap();
+        let unlabelled = cart(b"");
+
+        let (merged, conflicts) = merge_carts(&labelled, &labelled, &unlabelled).unwrap();
+        assert!(conflicts.is_empty());
+        assert_eq!(merged.label(), None);
+
+        let (merged, conflicts) = merge_carts(&unlabelled, &unlabelled, &labelled).unwrap();
+        assert!(conflicts.is_empty());
+        assert_eq!(merged.label(), labelled.label());
+
+        let mut relabelled = labelled.clone();
+        relabelled.set_label(vec![8; 128 * 128]).unwrap();
+        let (merged, conflicts) = merge_carts(&labelled, &relabelled, &unlabelled).unwrap();
+        assert_eq!(conflicts, ["label"]);
+        assert_eq!(merged.label(), relabelled.label());
+    }
+
+    #[test]
+    fn changes_to_the_same_line_keep_ours() {
+        let base = cart(b"a=1\nb=2\n");
+        let ours = cart(b"a=10\nb=2\n");
+        let theirs = cart(b"a=100\nb=2\n");
+        let (merged, conflicts) = merge_carts(&base, &ours, &theirs).unwrap();
+        assert_eq!(conflicts, ["code"]);
+        assert_eq!(merged, ours);
+    }
+}
@@ -1,14 +1,11 @@
-use crate::rom;
-use anyhow;
+use crate::error::Error;
 use packed_struct::prelude::*;
-use std::path::Path;
-
-pub fn dump(path: &Path) -> anyhow::Result<()> {
-    rom::dump_section::<Section>(path, 0x3100)
-}
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 
 /// See https://pico-8.fandom.com/wiki/Memory#Music
-#[derive(PackedStruct, Debug)]
+#[derive(PackedStruct, Serialize, Deserialize, Clone, Debug)]
+#[serde(try_from = "SectionData", into = "SectionData")]
 pub struct Section {
     #[packed_field(element_size_bytes = "4")]
     pub patterns: [Pattern; 64],
@@ -88,8 +85,90 @@ impl Default for Section {
     }
 }
 
-#[derive(PackedStruct, Debug, Default)]
+impl Section {
+    /// Songs start at pattern 0 and after any pattern that stops or is empty,
+    /// and play until they stop, loop, or reach an empty pattern.
+    /// Patterns after a loop back that isn't followed by a stop or a gap are never played by a song.
+    /// See https://pico-8.fandom.com/wiki/Music
+    pub fn songs(&self) -> Vec<Song> {
+        let starts = (0..self.patterns.len()).filter(|p| {
+            self.patterns[*p].enabled()
+                && (*p == 0
+                    || self.patterns[p - 1].stop_at_end()
+                    || !self.patterns[p - 1].enabled())
+        });
+        starts.map(|start| self.play(start)).collect()
+    }
+
+    /// Patterns played when music starts at `start`, as with `music(start)`.
+    pub fn play(&self, start: usize) -> Song {
+        let mut patterns = vec![];
+        let mut current = start;
+        loop {
+            patterns.push(current);
+            let pattern = &self.patterns[current];
+            if pattern.stop_at_end() {
+                return Song {
+                    patterns,
+                    end: SongEnd::Stop,
+                };
+            }
+            if pattern.loop_back() {
+                // PICO-8 goes back to the nearest loop start, or pattern 0 if there isn't one.
+                let to = (0..=current)
+                    .rev()
+                    .find(|p| self.patterns[*p].loop_start())
+                    .unwrap_or(0);
+                if patterns.contains(&to) {
+                    return Song {
+                        patterns,
+                        end: SongEnd::LoopBack(to),
+                    };
+                }
+                current = to;
+                continue;
+            }
+            current += 1;
+            if current >= self.patterns.len() || !self.patterns[current].enabled() {
+                return Song {
+                    patterns,
+                    end: SongEnd::RunOut,
+                };
+            }
+        }
+    }
+
+    /// Patterns that play each SFX on an enabled channel, by SFX number.
+    pub fn sfx_usage(&self) -> BTreeMap<u8, BTreeSet<usize>> {
+        let mut usage = BTreeMap::<u8, BTreeSet<usize>>::new();
+        for (p, pattern) in self.patterns.iter().enumerate() {
+            for channel in pattern.channels.iter().filter(|c| c.enabled()) {
+                usage.entry(u8::from(channel.sfx_id)).or_default().insert(p);
+            }
+        }
+        usage
+    }
+}
+
+/// Patterns in the order they are played, starting from the first one.
+#[derive(Debug, PartialEq)]
+pub struct Song {
+    pub patterns: Vec<usize>,
+    pub end: SongEnd,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SongEnd {
+    Stop,
+    /// Pattern that playback loops back to.
+    LoopBack(usize),
+    /// The next pattern is empty or there are no more patterns.
+    RunOut,
+}
+
+#[derive(PackedStruct, Serialize, Deserialize, Clone, Debug, Default)]
 #[packed_struct()]
+#[serde(try_from = "PatternData", into = "PatternData")]
 pub struct Pattern {
     #[packed_field(element_size_bytes = "1")]
     pub channels: [Channel; 4],
@@ -120,15 +199,53 @@ impl Pattern {
         self.channels[2].pattern_flag = val
     }
 
+    /// Channel 3's flag bit, which PICO-8 doesn't use but keeps.
+    pub fn unused_flag(&self) -> bool {
+        self.channels[3].pattern_flag
+    }
+
+    pub fn set_unused_flag(&mut self, val: bool) {
+        self.channels[3].pattern_flag = val
+    }
+
     pub fn enabled(&self) -> bool {
-        self.channels
+        self.channels.iter().any(|x| x.enabled())
+    }
+
+    /// Pattern number, SFX played on each channel, and flags, like `03  12 13 -- --  loop start`.
+    pub fn summary(&self, index: usize) -> String {
+        let channels = self
+            .channels
             .iter()
-            .fold(false, |acc, x| acc || x.enabled())
+            .map(|c| {
+                if c.enabled() {
+                    format!("{:02}", u8::from(c.sfx_id))
+                } else {
+                    "--".to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        let flags = [
+            (self.loop_start(), "loop start"),
+            (self.loop_back(), "loop back"),
+            (self.stop_at_end(), "stop"),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>()
+        .join(", ");
+        format!("{:02}  {}  {}", index, channels, flags)
+            .trim_end()
+            .to_string()
     }
 }
 
-#[derive(PackedStruct, Debug, Default)]
+/// Serialized without `pattern_flag`, which belongs to the pattern.
+#[derive(PackedStruct, Serialize, Deserialize, Clone, Debug, Default)]
 #[packed_struct(size_bytes = "1", bit_numbering = "lsb0")]
+#[serde(try_from = "ChannelData", into = "ChannelData")]
 pub struct Channel {
     #[packed_field(bits = "0..=5")]
     pub sfx_id: Integer<u8, packed_bits::Bits<6>>,
@@ -147,3 +264,212 @@ impl Channel {
         self.disabled = !val
     }
 }
+
+// Serialized forms use plain numbers and named flags instead of packed fields.
+
+#[derive(Serialize, Deserialize)]
+struct SectionData {
+    patterns: Vec<Pattern>,
+}
+
+impl From<Section> for SectionData {
+    fn from(section: Section) -> Self {
+        SectionData {
+            patterns: section.patterns.to_vec(),
+        }
+    }
+}
+
+impl TryFrom<SectionData> for Section {
+    type Error = Error;
+
+    fn try_from(data: SectionData) -> Result<Self, Error> {
+        let actual = data.patterns.len();
+        Ok(Section {
+            patterns: data.patterns.try_into().map_err(|_| Error::Count {
+                name: "patterns",
+                expected: 64,
+                actual,
+            })?,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct PatternData {
+    loop_start: bool,
+    loop_back: bool,
+    stop_at_end: bool,
+    /// Missing from dumps written before it was added.
+    #[serde(default)]
+    unused_flag: bool,
+    channels: Vec<Channel>,
+}
+
+impl From<Pattern> for PatternData {
+    fn from(pattern: Pattern) -> Self {
+        PatternData {
+            loop_start: pattern.loop_start(),
+            loop_back: pattern.loop_back(),
+            stop_at_end: pattern.stop_at_end(),
+            unused_flag: pattern.unused_flag(),
+            channels: pattern.channels.to_vec(),
+        }
+    }
+}
+
+impl TryFrom<PatternData> for Pattern {
+    type Error = Error;
+
+    fn try_from(data: PatternData) -> Result<Self, Error> {
+        let actual = data.channels.len();
+        let mut pattern = Pattern {
+            channels: data.channels.try_into().map_err(|_| Error::Count {
+                name: "channels",
+                expected: 4,
+                actual,
+            })?,
+        };
+        pattern.set_begin_loop(data.loop_start);
+        pattern.set_end_loop(data.loop_back);
+        pattern.set_stop_at_end(data.stop_at_end);
+        pattern.set_unused_flag(data.unused_flag);
+        Ok(pattern)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChannelData {
+    sfx_id: u8,
+    enabled: bool,
+}
+
+impl From<Channel> for ChannelData {
+    fn from(channel: Channel) -> Self {
+        ChannelData {
+            sfx_id: u8::from(channel.sfx_id),
+            enabled: channel.enabled(),
+        }
+    }
+}
+
+impl TryFrom<ChannelData> for Channel {
+    type Error = Error;
+
+    fn try_from(data: ChannelData) -> Result<Self, Error> {
+        if data.sfx_id > 63 {
+            return Err(Error::SfxId(data.sfx_id));
+        }
+        let mut channel = Channel {
+            sfx_id: Integer::from(data.sfx_id),
+            ..Channel::default()
+        };
+        channel.set_enabled(data.enabled);
+        Ok(channel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Music with a pattern for each entry: the SFX on its first channels, then loop start, loop back, and stop.
+    fn section(patterns: &[(&[u8], [bool; 3])]) -> Section {
+        let mut section = Section::default();
+        for (p, pattern) in section.patterns.iter_mut().enumerate() {
+            let (sfx_ids, [loop_start, loop_back, stop]) =
+                patterns.get(p).copied().unwrap_or_default();
+            for (c, channel) in pattern.channels.iter_mut().enumerate() {
+                match sfx_ids.get(c) {
+                    Some(sfx_id) => channel.sfx_id = Integer::from(*sfx_id),
+                    None => channel.set_enabled(false),
+                }
+            }
+            pattern.set_begin_loop(loop_start);
+            pattern.set_end_loop(loop_back);
+            pattern.set_stop_at_end(stop);
+        }
+        section
+    }
+
+    const NONE: [bool; 3] = [false; 3];
+    const LOOP_START: [bool; 3] = [true, false, false];
+    const LOOP_BACK: [bool; 3] = [false, true, false];
+    const STOP: [bool; 3] = [false, false, true];
+
+    #[test]
+    fn songs_stop_loop_back_or_run_out() {
+        let section = section(&[
+            (&[1], NONE),
+            (&[2, 3], STOP),
+            (&[4], LOOP_START),
+            (&[5], LOOP_BACK),
+            // Only reachable with `music(4)`.
+            (&[6], NONE),
+            (&[], NONE),
+            (&[7], NONE),
+            (&[7, 8], NONE),
+        ]);
+        let song = |patterns: Vec<usize>, end| Song { patterns, end };
+        assert_eq!(
+            vec![
+                song(vec![0, 1], SongEnd::Stop),
+                song(vec![2, 3], SongEnd::LoopBack(2)),
+                song(vec![6, 7], SongEnd::RunOut),
+            ],
+            section.songs()
+        );
+        assert_eq!(song(vec![4], SongEnd::RunOut), section.play(4));
+    }
+
+    #[test]
+    fn loop_back_without_loop_start_goes_to_pattern_0() {
+        let section = section(&[(&[1], NONE), (&[2], NONE), (&[3], LOOP_BACK)]);
+        assert_eq!(
+            Song {
+                patterns: vec![1, 2, 0, 1, 2],
+                end: SongEnd::LoopBack(0)
+            },
+            section.play(1)
+        );
+        // Running out at the last pattern.
+        let mut last = self::section(&[]);
+        last.patterns[63] = self::section(&[(&[9], NONE)]).patterns[0].clone();
+        assert_eq!(
+            Song {
+                patterns: vec![63],
+                end: SongEnd::RunOut
+            },
+            last.play(63)
+        );
+    }
+
+    #[test]
+    fn sfx_usage_counts_enabled_channels() {
+        let section = section(&[(&[1, 2], NONE), (&[2], STOP)]);
+        assert_eq!(
+            BTreeMap::from([(1, BTreeSet::from([0])), (2, BTreeSet::from([0, 1])),]),
+            section.sfx_usage()
+        );
+    }
+
+    #[test]
+    fn every_bit_round_trips_through_json() {
+        // 64 patterns of 4 channels hold every byte value once.
+        let bytes = (0..=255u8).collect::<Vec<_>>();
+        let section = Section::unpack_from_slice(&bytes).unwrap();
+        let json = serde_json::to_string(&section).unwrap();
+        let parsed: Section = serde_json::from_str(&json).unwrap();
+        assert_eq!(bytes, parsed.pack_to_vec().unwrap());
+    }
+
+    #[test]
+    fn unused_flag_defaults_to_off() {
+        let json = r#"{"loop_start": true, "loop_back": false, "stop_at_end": false, "channels": [
+            {"sfx_id": 1, "enabled": true}, {"sfx_id": 2, "enabled": false},
+            {"sfx_id": 3, "enabled": true}, {"sfx_id": 4, "enabled": true}
+        ]}"#;
+        let pattern: Pattern = serde_json::from_str(json).unwrap();
+        assert_eq!(vec![0x81, 0x42, 0x03, 0x04], pattern.pack_to_vec().unwrap());
+    }
+}
@@ -0,0 +1,315 @@
+use crate::cartridge::Cartridge;
+use crate::error::{Error, Result};
+use crate::gfx::{self, LABEL_SIZE};
+use crate::p8scii;
+use crate::rom::{GFF, GFX, MAP, MUSIC, SFX};
+use std::fmt::Write;
+
+/// Digits for label colors, which go up to 31 to include the extra palette.
+pub const LABEL_DIGITS: &[u8; 32] = b"0123456789abcdefghijklmnopqrstuv";
+
+/// Read a `.p8` text cartridge. Missing sections and lines are left as zeros.
+/// See https://pico-8.fandom.com/wiki/P8FileFormat
+pub fn parse(text: &str) -> Result<Cartridge> {
+    let mut cartridge = Cartridge::from_bytes(vec![0; Cartridge::LEN])?;
+    let mut code = String::new();
+    let mut label = vec![];
+    let mut section = "";
+    let mut row = 0;
+    for (n, line) in text.lines().enumerate() {
+        if let Some(name) = line
+            .strip_prefix("__")
+            .and_then(|l| l.strip_suffix("__"))
+            .filter(|name| {
+                !name.is_empty()
+                    && name
+                        .bytes()
+                        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || b"_:".contains(&c))
+            })
+        {
+            section = name;
+            row = 0;
+            continue;
+        }
+        if section != "lua" && line.is_empty() {
+            continue;
+        }
+        let error = |message| Error::P8 {
+            line: n + 1,
+            message,
+        };
+        let rows = match section {
+            "gfx" => 128,
+            "gff" => 2,
+            "label" => LABEL_SIZE,
+            "map" => 32,
+            "sfx" => 64,
+            "music" => 64,
+            _ => usize::MAX,
+        };
+        if row >= rows {
+            return Err(error("section has too many lines"));
+        }
+        match section {
+            "lua" => {
+                code.push_str(line);
+                code.push('\n');
+            }
+            "gfx" => {
+                let pixels = digits(line, 16).ok_or_else(|| error("expected hex digits"))?;
+                let bytes = cartridge.bytes_mut(GFX.start + row * 64..GFX.start + row * 64 + 64)?;
+                copy_row(bytes, &gfx::pack_pixels(&pixels))
+                    .ok_or_else(|| error("line is too long"))?;
+            }
+            "gff" | "map" => {
+                let bytes = hex_bytes(line).ok_or_else(|| error("expected hex bytes"))?;
+                let start = if section == "gff" {
+                    GFF.start
+                } else {
+                    MAP.start
+                } + row * 128;
+                copy_row(cartridge.bytes_mut(start..start + 128)?, &bytes)
+                    .ok_or_else(|| error("line is too long"))?;
+            }
+            "label" => {
+                let mut pixels = digits(line, 32).ok_or_else(|| error("expected label colors"))?;
+                if pixels.len() > LABEL_SIZE {
+                    return Err(error("line is too long"));
+                }
+                pixels.resize(LABEL_SIZE, 0);
+                label.extend(pixels);
+            }
+            "sfx" => {
+                let bytes = sfx_bytes(line).ok_or_else(|| error("expected an SFX"))?;
+                let start = SFX.start + row * 68;
+                cartridge
+                    .bytes_mut(start..start + 68)?
+                    .copy_from_slice(&bytes);
+            }
+            "music" => {
+                let bytes = pattern_bytes(line).ok_or_else(|| error("expected a pattern"))?;
+                let start = MUSIC.start + row * 4;
+                cartridge
+                    .bytes_mut(start..start + 4)?
+                    .copy_from_slice(&bytes);
+            }
+            _ => (),
+        }
+        row += 1;
+    }
+    code.pop();
+    cartridge.set_code(&p8scii::encode(&code)?)?;
+    if !label.is_empty() {
+        label.resize(LABEL_SIZE * LABEL_SIZE, 0);
+        cartridge.set_label(label)?;
+    }
+    Ok(cartridge)
+}
+
+/// Write a cartridge as a `.p8` file, leaving out trailing empty lines as PICO-8 does.
+pub fn write(cartridge: &Cartridge) -> Result<String> {
+    let mut text = "pico-8 cartridge // http://www.pico-8.com\nversion 42\n__lua__\n".to_string();
+    text.push_str(&p8scii::decode(&cartridge.code()?));
+    text.push('\n');
+    let code_end = text.len();
+
+    let rows = cartridge.bytes(GFX)?.chunks(64).map(|row| {
+        let pixels = gfx::unpack_pixels(row);
+        pixels.iter().map(|p| format!("{:x}", p)).collect()
+    });
+    section(&mut text, "gfx", rows);
+    if let Some(label) = cartridge.label() {
+        let rows = label.chunks(LABEL_SIZE).map(|row| {
+            row.iter()
+                .map(|p| LABEL_DIGITS[*p as usize] as char)
+                .collect()
+        });
+        section(&mut text, "label", rows);
+    }
+    section(&mut text, "gff", cartridge.bytes(GFF)?.chunks(128).map(hex));
+    section(&mut text, "map", cartridge.bytes(MAP)?.chunks(128).map(hex));
+    let sfx = cartridge.bytes(SFX)?.chunks(68).map(|sfx| {
+        let mut line = hex(&sfx[64..68]);
+        for note in sfx[..64].chunks(2) {
+            let note = u16::from_le_bytes([note[0], note[1]]);
+            let waveform = (note >> 6 & 0x7) | (note >> 15) << 3;
+            let _ = write!(
+                line,
+                "{:02x}{:x}{:x}{:x}",
+                note & 0x3f,
+                waveform,
+                note >> 9 & 0x7,
+                note >> 12 & 0x7
+            );
+        }
+        line
+    });
+    section(&mut text, "sfx", sfx);
+    let music = cartridge.bytes(MUSIC)?.chunks(4).map(|pattern| {
+        let flags = (0..4).fold(0, |flags, c| flags | (pattern[c] >> 7) << c);
+        let channels = pattern.iter().map(|c| c & 0x7f).collect::<Vec<_>>();
+        format!("{:02x} {}", flags, hex(&channels))
+    });
+    section(&mut text, "music", music);
+    // The last section ends with an empty line, but code with nothing after it would gain one.
+    if text.len() > code_end {
+        text.push('\n');
+    }
+    Ok(text)
+}
+
+/// Add a section, unless every line of it is empty.
+fn section(text: &mut String, name: &str, rows: impl Iterator<Item = String>) {
+    let mut rows = rows.collect::<Vec<_>>();
+    while rows
+        .last()
+        .is_some_and(|row| row.bytes().all(|c| b"0 ".contains(&c)))
+    {
+        rows.pop();
+    }
+    if rows.is_empty() {
+        return;
+    }
+    let _ = writeln!(text, "__{}__", name);
+    for row in rows {
+        text.push_str(&row);
+        text.push('\n');
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Value of each digit in a line, in base 16 or 32.
+fn digits(line: &str, radix: u32) -> Option<Vec<u8>> {
+    line.chars()
+        .map(|c| Some(c.to_digit(radix)? as u8))
+        .collect()
+}
+
+fn hex_bytes(line: &str) -> Option<Vec<u8>> {
+    let digits = digits(line, 16)?;
+    if digits.len() % 2 != 0 {
+        return None;
+    }
+    Some(digits.chunks(2).map(|d| d[0] << 4 | d[1]).collect())
+}
+
+/// Copy a possibly shorter row of bytes, or return `None` if it's longer.
+fn copy_row(dest: &mut [u8], row: &[u8]) -> Option<()> {
+    dest.get_mut(..row.len())?.copy_from_slice(row);
+    Some(())
+}
+
+/// An SFX line has its switches, speed, and loop points as hex bytes,
+/// then 5 digits per note: pitch, waveform with 8 set for custom instruments, volume, and effect.
+fn sfx_bytes(line: &str) -> Option<Vec<u8>> {
+    let digits = digits(line, 16)?;
+    if digits.len() != 8 + 32 * 5 {
+        return None;
+    }
+    let mut bytes = vec![];
+    for note in digits[8..].chunks(5) {
+        let (pitch, waveform) = ((note[0] << 4 | note[1]) as u16, note[2] as u16);
+        if pitch > 0x3f || note[3] > 7 || note[4] > 7 {
+            return None;
+        }
+        let note = pitch
+            | (waveform & 0x7) << 6
+            | (note[3] as u16) << 9
+            | (note[4] as u16) << 12
+            | (waveform >> 3) << 15;
+        bytes.extend(note.to_le_bytes());
+    }
+    bytes.extend(hex_bytes(&line[..8])?);
+    Some(bytes)
+}
+
+/// A music line has flags for loop start, loop back, and stop, then a space and a byte per channel.
+/// In a ROM, each flag is the high bit of one channel.
+fn pattern_bytes(line: &str) -> Option<Vec<u8>> {
+    let (flags, channels) = line.split_once(' ')?;
+    let flags = hex_bytes(flags)?;
+    let channels = hex_bytes(channels)?;
+    if flags.len() != 1 || channels.len() != 4 || channels.iter().any(|c| c & 0x80 != 0) {
+        return None;
+    }
+    Some(
+        channels
+            .iter()
+            .enumerate()
+            .map(|(c, channel)| channel | (flags[0] >> c & 1) << 7)
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A cart with a little of everything, padded the way PICO-8 writes it.
+    fn cart() -> String {
+        let pad = |line: &str, len: usize| format!("{}{}", line, "0".repeat(len - line.len()));
+        [
+            "pico-8 cartridge // http://www.pico-8.com",
+            "version 42",
+            "__lua__",
+            "-- \u{2665}",
+            "print(\"\u{1f17e}\u{fe0f}\")",
+            "__gfx__",
+            &pad("01234567", 128),
+            "__label__",
+            &pad("0v", 128),
+            "__gff__",
+            &pad("0001", 256),
+            "__map__",
+            &pad("0102", 256),
+            "__sfx__",
+            &pad("010c002018050", 168),
+            "__music__",
+            "01 41424344",
+            "04 00014243",
+            "",
+            "",
+        ]
+        .join("\n")
+    }
+
+    #[test]
+    fn p8_files_round_trip() {
+        let cartridge = parse(&cart()).unwrap();
+        assert_eq!(
+            b"-- \x87\nprint(\"\x8e\")".to_vec(),
+            cartridge.code().unwrap()
+        );
+        assert_eq!(&[0x10, 0x32, 0x54, 0x76], cartridge.bytes(0..4).unwrap());
+        assert_eq!(&[0, 31, 0], &cartridge.label().unwrap()[..3]);
+        assert_eq!(&[0, 1], cartridge.bytes(GFF.start..GFF.start + 2).unwrap());
+        assert_eq!(&[1, 2], cartridge.bytes(MAP.start..MAP.start + 2).unwrap());
+
+        let sfx = cartridge.sfx().unwrap();
+        assert_eq!(12, sfx.sfxes[0].speed);
+        assert_eq!(24, u8::from(sfx.sfxes[0].notes[0].pitch()));
+        assert_eq!(5, u8::from(sfx.sfxes[0].notes[0].volume()));
+
+        let music = cartridge.music().unwrap();
+        assert!(music.patterns[0].loop_start() && !music.patterns[0].enabled());
+        assert!(music.patterns[1].stop_at_end());
+        assert_eq!(1, u8::from(music.patterns[1].channels[1].sfx_id));
+
+        assert_eq!(cart(), write(&cartridge).unwrap());
+    }
+
+    #[test]
+    fn malformed_lines_are_an_error() {
+        assert!(matches!(
+            parse("__lua__\n__music__\n00 41424344\n0041424344\n"),
+            Err(Error::P8 {
+                line: 4,
+                message: "expected a pattern"
+            })
+        ));
+        assert!(parse("__gff__\n00\n00\n00\n").is_err());
+    }
+}
@@ -0,0 +1,255 @@
+use crate::cartridge::Cartridge;
+use crate::error::{Error, Result};
+use crate::gfx::{self, EXTRA_PALETTE, LABEL_SIZE, PALETTE};
+use crate::lua;
+use crate::p8scii;
+use crate::rom::CODE;
+
+/// Width and height of a `.p8.png` cartridge image.
+pub const WIDTH: usize = 160;
+pub const HEIGHT: usize = 205;
+/// Top left corner of the label in the cartridge image.
+pub const LABEL_X: usize = 16;
+pub const LABEL_Y: usize = 24;
+/// Where PICO-8 writes the title and author lines, under the label.
+pub const TITLE_X: usize = 18;
+pub const TITLE_Y: usize = 166;
+pub const AUTHOR_Y: usize = 176;
+
+/// Read a `.p8.png` cartridge. Each byte of the ROM is hidden in the low two bits of
+/// one pixel's alpha, red, green, and blue, and the label is drawn on the cartridge.
+/// See https://pico-8.fandom.com/wiki/P8PNGFileFormat
+pub fn parse(bytes: &[u8]) -> Result<Cartridge> {
+    let (width, height, pixels) = gfx::read_png(bytes)?;
+    if (width as usize, height as usize) != (WIDTH, HEIGHT) {
+        return Err(Error::Png(format!(
+            "cartridge images are {}×{}, not {}×{}",
+            WIDTH, HEIGHT, width, height
+        )));
+    }
+    let rom = pixels
+        .iter()
+        .take(Cartridge::LEN)
+        .map(|[r, g, b, a]| (a & 3) << 6 | (r & 3) << 4 | (g & 3) << 2 | (b & 3))
+        .collect();
+    let mut cartridge = Cartridge::from_bytes(rom)?;
+    let label = (0..LABEL_SIZE)
+        .flat_map(|y| {
+            let start = (LABEL_Y + y) * WIDTH + LABEL_X;
+            pixels[start..start + LABEL_SIZE].iter()
+        })
+        .map(|[r, g, b, _]| gfx::nearest_color([*r, *g, *b]))
+        .collect();
+    cartridge.set_label(label)?;
+    Ok(cartridge)
+}
+
+/// Draw a cartridge with the label and the title and author from the code's first two comments,
+/// hide the ROM in it, and check that it reads back the same.
+/// Uncompressed code is compressed first, since that's how PICO-8 saves it.
+pub fn write(cartridge: &Cartridge) -> Result<Vec<u8>> {
+    let mut cartridge = cartridge.clone();
+    let code = cartridge.code()?;
+    let stored = cartridge.bytes(CODE)?;
+    if !(stored.starts_with(b"\0pxa") || stored.starts_with(b":c:\0")) {
+        cartridge.set_code(&code)?;
+    }
+
+    let mut image = template();
+    let label = cartridge.label().map(<[u8]>::to_vec);
+    let label = label.unwrap_or_else(|| vec![0; LABEL_SIZE * LABEL_SIZE]);
+    for (i, color) in label.iter().enumerate() {
+        let (x, y) = (LABEL_X + i % LABEL_SIZE, LABEL_Y + i / LABEL_SIZE);
+        image[y * WIDTH + x] = color_rgb(*color);
+    }
+    let title = lua::title(&code);
+    for (text, (y, color)) in title
+        .iter()
+        .zip([(TITLE_Y, PALETTE[7]), (AUTHOR_Y, PALETTE[6])])
+    {
+        draw_text(&mut image, TITLE_X, y, &p8scii::encode(text)?, color);
+    }
+
+    let rom = cartridge.as_bytes();
+    let mut rgba = Vec::with_capacity(WIDTH * HEIGHT * 4);
+    for (i, [r, g, b]) in image.iter().enumerate() {
+        let byte = rom.get(i).copied().unwrap_or_default();
+        rgba.extend([
+            r & !3 | (byte >> 4) & 3,
+            g & !3 | (byte >> 2) & 3,
+            b & !3 | byte & 3,
+            0xfc | byte >> 6,
+        ]);
+    }
+    let png = encode_rgba(&rgba)?;
+
+    let check = parse(&png)?;
+    if check.as_bytes() != rom || cartridge.label().is_some_and(|l| check.label() != Some(l)) {
+        return Err(Error::Png(
+            "cartridge image doesn't read back the same".to_string(),
+        ));
+    }
+    Ok(png)
+}
+
+fn color_rgb(color: u8) -> [u8; 3] {
+    match color {
+        0..=15 => PALETTE[color as usize],
+        _ => EXTRA_PALETTE[(color & 0xf) as usize],
+    }
+}
+
+/// Colors of the cartridge template.
+const CASE: [u8; 3] = [0x2b, 0x2b, 0x33];
+const RIDGE: [u8; 3] = [0x1b, 0x1b, 0x22];
+const OUTLINE: [u8; 3] = [0x00, 0x00, 0x00];
+/// The black window the label sits in, and the dark strip the title and author are written on.
+const WINDOW: [u8; 3] = [0x00, 0x00, 0x00];
+const STRIP: [u8; 3] = [0x11, 0x11, 0x16];
+
+/// The PICO-8 cartridge: a case with a cut top right corner and grip ridges across the top,
+/// the label in a black window at 16,24, and a strip under it for the title and author lines.
+fn template() -> Vec<[u8; 3]> {
+    // Size of the cut corner, and the border around the label and the strip.
+    const CUT: usize = 12;
+    const BORDER: usize = 2;
+    let window_x = LABEL_X - BORDER..LABEL_X + LABEL_SIZE + BORDER;
+    let window_y = LABEL_Y - BORDER..LABEL_Y + LABEL_SIZE + BORDER;
+    let strip_y = TITLE_Y - 4..AUTHOR_Y + 9;
+    let mut image = vec![CASE; WIDTH * HEIGHT];
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            let from_corner = WIDTH - 1 - x + y;
+            let edge = x == 0 || y == 0 || x == WIDTH - 1 || y == HEIGHT - 1;
+            image[y * WIDTH + x] = if edge || from_corner <= CUT {
+                OUTLINE
+            } else if window_x.contains(&x) && window_y.contains(&y) {
+                WINDOW
+            } else if window_x.contains(&x) && strip_y.contains(&y) {
+                STRIP
+            } else if (6..LABEL_Y - 6).contains(&y) && y % 3 == 0 && (24..WIDTH - 24).contains(&x) {
+                RIDGE
+            } else {
+                CASE
+            };
+        }
+    }
+    image
+}
+
+/// Draw P8SCII text in a 3×5 font, 4 pixels per character, cut off at the edge of the case.
+/// Glyphs outside of ASCII are drawn as spaces.
+fn draw_text(image: &mut [[u8; 3]], x: usize, y: usize, text: &[u8], color: [u8; 3]) {
+    for (i, c) in text.iter().enumerate() {
+        let left = x + i * 4;
+        if left + 3 > WIDTH - x {
+            break;
+        }
+        let glyph = match c {
+            0x20..=0x7e => FONT[*c as usize - 0x20],
+            _ => 0,
+        };
+        for row in 0..5 {
+            for col in 0..3 {
+                if glyph >> (14 - row * 3 - col) & 1 == 1 {
+                    image[(y + row) * WIDTH + left + col] = color;
+                }
+            }
+        }
+    }
+}
+
+fn encode_rgba(rgba: &[u8]) -> Result<Vec<u8>> {
+    let png_error = |e: png::EncodingError| Error::Png(e.to_string());
+    let mut png = vec![];
+    let mut encoder = png::Encoder::new(&mut png, WIDTH as u32, HEIGHT as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(png_error)?;
+    writer.write_image_data(rgba).map_err(png_error)?;
+    writer.finish().map_err(png_error)?;
+    Ok(png)
+}
+
+/// 3×5 glyphs for ASCII 0x20–0x7e, a bit per pixel from the top left, with the top row in the high bits.
+/// Lowercase letters look like capitals, as they do in PICO-8.
+const FONT: [u16; 95] = [
+    0x0000, 0x2482, 0x5a00, 0x5f7d, 0x7ddf, 0x52a5, 0x6def, 0x2400, 0x1491, 0x4494, 0x55d5, 0x05d0,
+    0x0014, 0x01c0, 0x0002, 0x12a4, 0x7b6f, 0x6497, 0x73e7, 0x72cf, 0x5bc9, 0x79cf, 0x49ef, 0x7249,
+    0x7bef, 0x7bc9, 0x0410, 0x0414, 0x1511, 0x0e38, 0x4454, 0x72c2, 0x2b63, 0x7bed, 0x7baf, 0x7927,
+    0x6b6f, 0x79a7, 0x79a4, 0x792f, 0x5bed, 0x7497, 0x7496, 0x5bad, 0x4927, 0x7f6d, 0x6b6d, 0x3b6e,
+    0x7be4, 0x2b73, 0x7bad, 0x39ce, 0x7492, 0x5b6b, 0x5b7a, 0x5b7f, 0x5aad, 0x5bcf, 0x72a7, 0x6926,
+    0x4889, 0x324b, 0x2a00, 0x0007, 0x2200, 0x7bed, 0x7baf, 0x7927, 0x6b6f, 0x79a7, 0x79a4, 0x792f,
+    0x5bed, 0x7497, 0x7496, 0x5bad, 0x4927, 0x7f6d, 0x6b6d, 0x3b6e, 0x7be4, 0x2b73, 0x7bad, 0x39ce,
+    0x7492, 0x5b6b, 0x5b7a, 0x5b7f, 0x5aad, 0x5bcf, 0x72a7, 0x3593, 0x2492, 0x64d6, 0x03e0,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cartridge_images_round_trip() {
+        let mut cartridge = Cartridge::from_bytes(vec![0x5a; Cartridge::LEN]).unwrap();
+        cartridge
+            .set_code(b"-- my game\n-- by \x87\nprint(1)")
+            .unwrap();
+        let label = (0..LABEL_SIZE * LABEL_SIZE)
+            .map(|i| (i % 32) as u8)
+            .collect();
+        cartridge.set_label(label).unwrap();
+
+        let read = parse(&write(&cartridge).unwrap()).unwrap();
+        assert_eq!(cartridge, read);
+    }
+
+    #[test]
+    fn label_title_and_author_are_drawn_on_the_template() {
+        let mut cartridge = Cartridge::from_bytes(vec![0; Cartridge::LEN]).unwrap();
+        cartridge.set_code(b"-- my game\n-- by me").unwrap();
+        cartridge
+            .set_label(vec![8; LABEL_SIZE * LABEL_SIZE])
+            .unwrap();
+        let (_, _, pixels) = gfx::read_png(&write(&cartridge).unwrap()).unwrap();
+        // The low two bits of each channel hold the ROM.
+        let rgb = |x: usize, y: usize| {
+            let [r, g, b, _] = pixels[y * WIDTH + x];
+            [r & !3, g & !3, b & !3]
+        };
+        let template = template();
+        let expected = |x: usize, y: usize| template[y * WIDTH + x].map(|c| c & !3);
+
+        let (left, top) = (LABEL_X, LABEL_Y);
+        let (right, bottom) = (LABEL_X + LABEL_SIZE, LABEL_Y + LABEL_SIZE);
+        for x in left - 3..right + 3 {
+            for y in [top - 3, top - 2, top - 1, bottom, bottom + 1, bottom + 2] {
+                assert_eq!(expected(x, y), rgb(x, y), "({}, {})", x, y);
+            }
+        }
+        for y in top - 3..bottom + 3 {
+            for x in [left - 3, left - 2, left - 1, right, right + 1, right + 2] {
+                assert_eq!(expected(x, y), rgb(x, y), "({}, {})", x, y);
+            }
+        }
+        assert_eq!(WINDOW.map(|c| c & !3), rgb(left - 1, top - 1));
+        assert_eq!(CASE.map(|c| c & !3), rgb(left - 3, top - 3));
+        assert_eq!(color_rgb(8).map(|c| c & !3), rgb(left, top));
+
+        let color = |c: u8| color_rgb(c).map(|c| c & !3);
+        let drawn = |y: usize, c: u8| {
+            (y..y + 5).any(|y| (TITLE_X..TITLE_X + 40).any(|x| rgb(x, y) == color(c)))
+        };
+        assert!(drawn(TITLE_Y, 7));
+        assert!(drawn(AUTHOR_Y, 6));
+        assert_eq!(STRIP.map(|c| c & !3), rgb(TITLE_X - 1, TITLE_Y));
+    }
+
+    #[test]
+    fn uncompressed_code_is_compressed() {
+        let mut rom = vec![0; Cartridge::LEN];
+        rom[CODE.start..CODE.start + 8].copy_from_slice(b"print(1)");
+        let read = parse(&write(&Cartridge::from_bytes(rom).unwrap()).unwrap()).unwrap();
+        assert!(read.bytes(CODE).unwrap().starts_with(b"\0pxa"));
+        assert_eq!(b"print(1)".to_vec(), read.code().unwrap());
+    }
+}
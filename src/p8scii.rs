@@ -0,0 +1,104 @@
+use crate::error::{Error, Result};
+
+/// Control codes 0x00–0x1f, as PICO-8 writes them in `.p8` files.
+/// Tab, newline, and carriage return stay as they are.
+/// See https://pico-8.fandom.com/wiki/P8SCII
+const CONTROL: [&str; 32] = [
+    "\0", "¹", "²", "³", "⁴", "⁵", "⁶", "⁷", "⁸", "\t", "\n", "ᵇ", "ᶜ", "\r", "ᵉ", "ᶠ", "▮", "■",
+    "□", "⁙", "⁘", "‖", "◀", "▶", "「", "」", "¥", "•", "、", "。", "゛", "゜",
+];
+
+/// Glyphs 0x80–0xff: symbols, then hiragana, then katakana.
+/// Some symbols are emoji with a variation selector.
+const GLYPHS: [&str; 128] = [
+    "█", "▒", "🐱", "⬇️", "░", "✽", "●", "♥", "☉", "웃", "⌂", "⬅️", "😐", "♪", "🅾️", "◆", "…",
+    "➡️", "★", "⧗", "⬆️", "ˇ", "∧", "❎", "▤", "▥", "あ", "い", "う", "え", "お", "か", "き", "く",
+    "け", "こ", "さ", "し", "す", "せ", "そ", "た", "ち", "つ", "て", "と", "な", "に", "ぬ", "ね",
+    "の", "は", "ひ", "ふ", "へ", "ほ", "ま", "み", "む", "め", "も", "や", "ゆ", "よ", "ら", "り",
+    "る", "れ", "ろ", "わ", "を", "ん", "っ", "ゃ", "ゅ", "ょ", "ア", "イ", "ウ", "エ", "オ", "カ",
+    "キ", "ク", "ケ", "コ", "サ", "シ", "ス", "セ", "ソ", "タ", "チ", "ツ", "テ", "ト", "ナ", "ニ",
+    "ヌ", "ネ", "ノ", "ハ", "ヒ", "フ", "ヘ", "ホ", "マ", "ミ", "ム", "メ", "モ", "ヤ", "ユ", "ヨ",
+    "ラ", "リ", "ル", "レ", "ロ", "ワ", "ヲ", "ン", "ッ", "ャ", "ュ", "ョ", "◜", "◝",
+];
+
+/// Printable ASCII as strings, so that `char` can return every P8SCII character the same way.
+const ASCII: [&str; 95] = [
+    " ", "!", "\"", "#", "$", "%", "&", "'", "(", ")", "*", "+", ",", "-", ".", "/", "0", "1", "2",
+    "3", "4", "5", "6", "7", "8", "9", ":", ";", "<", "=", ">", "?", "@", "A", "B", "C", "D", "E",
+    "F", "G", "H", "I", "J", "K", "L", "M", "N", "O", "P", "Q", "R", "S", "T", "U", "V", "W", "X",
+    "Y", "Z", "[", "\\", "]", "^", "_", "`", "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k",
+    "l", "m", "n", "o", "p", "q", "r", "s", "t", "u", "v", "w", "x", "y", "z", "{", "|", "}", "~",
+];
+
+/// Unicode for a P8SCII byte. 0x20–0x7e are ASCII, where `A`–`Z` are PICO-8's small capitals.
+pub fn char(byte: u8) -> &'static str {
+    match byte {
+        0x00..=0x1f => CONTROL[byte as usize],
+        0x20..=0x7e => ASCII[byte as usize - 0x20],
+        0x7f => "○",
+        0x80..=0xff => GLYPHS[byte as usize - 0x80],
+    }
+}
+
+/// Convert P8SCII to Unicode, as PICO-8 does when saving a `.p8` file.
+pub fn decode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| char(*b)).collect()
+}
+
+/// Convert Unicode to P8SCII, as PICO-8 does when loading a `.p8` file.
+/// Emoji glyphs may leave out their variation selector.
+pub fn encode(text: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c.is_ascii() {
+            bytes.push(c as u8);
+            rest = &rest[1..];
+            continue;
+        }
+        let (byte, len) = (0..=255u8)
+            .filter_map(|b| {
+                let s = char(b);
+                let bare = s.strip_suffix('\u{fe0f}').unwrap_or(s);
+                [s, bare]
+                    .into_iter()
+                    .find(|s| rest.starts_with(s))
+                    .map(|s| (b, s.len()))
+            })
+            .max_by_key(|(_, len)| *len)
+            .ok_or(Error::P8scii(c))?;
+        bytes.push(byte);
+        rest = &rest[len..];
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_byte_round_trips() {
+        let bytes = (0..=255u8).collect::<Vec<_>>();
+        let text = decode(&bytes);
+        assert_eq!(bytes, encode(&text).unwrap());
+        for b in bytes {
+            assert_eq!(vec![b], encode(char(b)).unwrap());
+        }
+    }
+
+    #[test]
+    fn glyphs_match_pico8() {
+        assert_eq!(
+            "print(\"🅾️❎⬆️ ねこ\")",
+            decode(b"print(\"\x8e\x97\x94 \xb1\xa3\")")
+        );
+        assert_eq!("Hi ○█◝", decode(b"Hi \x7f\x80\xff"));
+        assert_eq!(b"\x8e\x8b".to_vec(), encode("🅾⬅️").unwrap());
+    }
+
+    #[test]
+    fn unknown_characters_are_an_error() {
+        assert!(matches!(encode("café"), Err(Error::P8scii('é'))));
+    }
+}
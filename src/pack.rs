@@ -0,0 +1,243 @@
+use pico8_rom::rom::SFX;
+use pico8_rom::Cartridge;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+/// Bytes of a data cart that `reload` can read from: everything before the code.
+const SPACE: usize = SFX.end;
+
+/// Where one piece of a blob went. Blobs too big for one cart are split across several.
+#[derive(Serialize, Debug)]
+struct Part {
+    name: String,
+    file: PathBuf,
+    cart: String,
+    address: usize,
+    length: usize,
+    /// Offset of this part in the blob.
+    offset: usize,
+}
+
+/// Lay binary files into the gfx, map, and SFX regions of data carts named after `output`,
+/// starting a new cart when a blob doesn't fit in the rest of the current one,
+/// then print Lua constants for loading them with `reload`.
+pub fn pack(blobs: &[PathBuf], output: &Path, manifest: Option<&Path>) -> anyhow::Result<()> {
+    let blobs = blobs
+        .iter()
+        .map(|file| Ok((file.clone(), fs::read(file)?)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let (carts, parts) = layout(&blobs, output)?;
+    for (i, cart) in carts.iter().enumerate() {
+        cart.write(&output.with_file_name(cart_name(output, i)?))?;
+    }
+    if let Some(manifest) = manifest {
+        serde_json::to_writer_pretty(BufWriter::new(File::create(manifest)?), &parts)?;
+    }
+    print!("{}", loader(&parts));
+    Ok(())
+}
+
+/// Data carts holding each file's bytes, and where each part of each file went.
+fn layout(
+    blobs: &[(PathBuf, Vec<u8>)],
+    output: &Path,
+) -> anyhow::Result<(Vec<Cartridge>, Vec<Part>)> {
+    let mut carts: Vec<Cartridge> = vec![];
+    let mut parts = vec![];
+    let mut names = BTreeSet::new();
+    let mut used = SPACE;
+    for (file, data) in blobs {
+        let name = unique_name(lua_name(file), &mut names);
+        if data.len() <= SPACE && used + data.len() > SPACE {
+            used = SPACE;
+        }
+        let mut offset = 0;
+        loop {
+            if used == SPACE {
+                carts.push(Cartridge::from_bytes(vec![0; Cartridge::LEN])?);
+                used = 0;
+            }
+            let length = (data.len() - offset).min(SPACE - used);
+            carts
+                .last_mut()
+                .unwrap()
+                .bytes_mut(used..used + length)?
+                .copy_from_slice(&data[offset..offset + length]);
+            parts.push(Part {
+                name: name.clone(),
+                file: file.clone(),
+                cart: cart_name(output, carts.len() - 1)?,
+                address: used,
+                length,
+                offset,
+            });
+            used += length;
+            offset += length;
+            if offset == data.len() {
+                break;
+            }
+        }
+    }
+    Ok((carts, parts))
+}
+
+/// File name of each data cart: `data.p8`, then `data2.p8`, `data3.p8`, and so on.
+fn cart_name(output: &Path, index: usize) -> anyhow::Result<String> {
+    let name = output
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = [".p8.rom", ".p8.png", ".p8"]
+        .into_iter()
+        .find(|extension| name.ends_with(extension))
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Not a supported PICO-8 cartridge format (expected .p8.rom, .p8, or .p8.png): {}",
+                output.to_string_lossy()
+            )
+        })?;
+    if index == 0 {
+        return Ok(name);
+    }
+    let stem = &name[..name.len() - extension.len()];
+    Ok(format!("{}{}{}", stem, index + 1, extension))
+}
+
+/// A Lua name from a file name, like `level_1` for `Level-1.bin`.
+fn lua_name(file: &Path) -> String {
+    let stem = file
+        .file_stem()
+        .map(|x| x.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let name: String = stem
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | '0'..='9' | '_' => c,
+            _ => '_',
+        })
+        .collect();
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+/// `name`, or `name_2`, `name_3`, and so on if files with the same stem already took it.
+fn unique_name(name: String, names: &mut BTreeSet<String>) -> String {
+    let name = (1..)
+        .map(|n| match n {
+            1 => name.clone(),
+            n => format!("{}_{}", name, n),
+        })
+        .find(|name| !names.contains(name))
+        .unwrap();
+    names.insert(name.clone());
+    name
+}
+
+/// A table of `{cart, address, length}` parts for each blob, in order.
+fn loader(parts: &[Part]) -> String {
+    let mut lua =
+        "-- {cart,address,length} for each part: reload(dest,address,length,cart)\n".to_string();
+    let mut parts = parts.iter().peekable();
+    while let Some(first) = parts.next() {
+        let mut line = format!("{}={{", first.name);
+        let mut part = Some(first);
+        while let Some(p) = part {
+            let _ = write!(
+                line,
+                "{{\"{}\",0x{:04x},0x{:04x}}}",
+                p.cart, p.address, p.length
+            );
+            part = parts.next_if(|next| next.file == p.file && next.offset > 0);
+            if part.is_some() {
+                line.push(',');
+            }
+        }
+        lua.push_str(&line);
+        lua.push_str("}\n");
+    }
+    lua
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blob(name: &str, len: usize, byte: u8) -> (PathBuf, Vec<u8>) {
+        (PathBuf::from(name), vec![byte; len])
+    }
+
+    #[test]
+    fn blobs_start_new_carts_or_split_across_them() {
+        let blobs = [
+            blob("a.bin", 0x3000, 1),
+            // Doesn't fit in the 0x1300 bytes left, so it starts the second cart.
+            blob("b.bin", 0x2000, 2),
+            // Bigger than a cart, so it fills the rest of the second and goes on into a third.
+            blob("c.bin", 0x5000, 3),
+        ];
+        let (carts, parts) = layout(&blobs, Path::new("out/data.p8")).unwrap();
+        assert_eq!(carts.len(), 3);
+        let layout = parts
+            .iter()
+            .map(|p| {
+                (
+                    p.name.as_str(),
+                    p.cart.as_str(),
+                    p.address,
+                    p.length,
+                    p.offset,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            layout,
+            [
+                ("a", "data.p8", 0, 0x3000, 0),
+                ("b", "data2.p8", 0, 0x2000, 0),
+                ("c", "data2.p8", 0x2000, 0x2300, 0),
+                ("c", "data3.p8", 0, 0x2d00, 0x2300),
+            ]
+        );
+        assert_eq!(carts[1].as_bytes()[0x1fff..0x2001], [2, 3]);
+        assert_eq!(carts[2].as_bytes()[0x2cff..0x2d01], [3, 0]);
+        assert_eq!(
+            loader(&parts),
+            "-- {cart,address,length} for each part: reload(dest,address,length,cart)\n\
+             a={{\"data.p8\",0x0000,0x3000}}\n\
+             b={{\"data2.p8\",0x0000,0x2000}}\n\
+             c={{\"data2.p8\",0x2000,0x2300},{\"data3.p8\",0x0000,0x2d00}}\n"
+        );
+    }
+
+    #[test]
+    fn cart_names_count_up_before_the_extension() {
+        let name = |output, index| cart_name(Path::new(output), index).unwrap();
+        assert_eq!(name("data.p8", 0), "data.p8");
+        assert_eq!(name("out/data.p8.png", 1), "data2.p8.png");
+        assert_eq!(name("data.p8.rom", 9), "data10.p8.rom");
+        assert!(cart_name(Path::new("data.txt"), 0).is_err());
+    }
+
+    #[test]
+    fn lua_names_are_valid_and_unique() {
+        assert_eq!(lua_name(Path::new("levels/Level-1.bin")), "level_1");
+        assert_eq!(lua_name(Path::new("1up.bin")), "_1up");
+        assert_eq!(lua_name(Path::new(".bin")), "_bin");
+        let blobs = [
+            blob("a/x.bin", 1, 0),
+            blob("b/x.bin", 1, 0),
+            blob("x_2.bin", 1, 0),
+            blob("c/x.dat", 1, 0),
+        ];
+        let (_, parts) = layout(&blobs, Path::new("data.p8")).unwrap();
+        let names = parts.iter().map(|p| p.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["x", "x_2", "x_2_2", "x_3"]);
+    }
+}
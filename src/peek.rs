@@ -0,0 +1,123 @@
+use pico8_rom::rom::{CODE, GFF, GFX, MAP, MAP_SHARED, MUSIC, SFX};
+use pico8_rom::{lua, p8scii, Cartridge};
+use std::io::Write;
+use std::ops::Range;
+use std::path::Path;
+
+/// How to print the bytes.
+#[derive(Clone, Copy, Debug)]
+pub enum Encoding {
+    /// Rows of 16 bytes in hex, after the address of the first.
+    Hex,
+    /// The bytes themselves.
+    Bin,
+    /// A Lua string literal holding the bytes, as Unicode text like in a `.p8` file.
+    LuaString,
+}
+
+/// Byte range of a named region, like `map` or `sfx`.
+fn region(name: &str) -> Option<Range<usize>> {
+    Some(match name {
+        "gfx" => GFX,
+        // The bottom half of the sprite sheet, which is also the bottom half of the map.
+        "gfx2" => MAP_SHARED,
+        "map" => MAP,
+        "flags" => GFF,
+        "music" => MUSIC,
+        "sfx" => SFX,
+        "code" => CODE,
+        _ => return None,
+    })
+}
+
+/// A decimal or `0x` hex number.
+pub fn parse_number(text: &str) -> Result<usize, String> {
+    match text.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => text.parse(),
+    }
+    .map_err(|e| format!("{}: {}", text, e))
+}
+
+/// Byte range of a named region, or of `len` bytes from an address within the ROM.
+fn range(start: &str, len: Option<usize>) -> anyhow::Result<Range<usize>> {
+    Ok(match (region(start), len) {
+        (Some(_), Some(_)) => anyhow::bail!("A length can't be given with a named region"),
+        (Some(range), None) => range,
+        (None, Some(len)) => {
+            let start = parse_number(start).map_err(anyhow::Error::msg)?;
+            match start.checked_add(len).filter(|end| *end <= Cartridge::LEN) {
+                Some(end) => start..end,
+                None => anyhow::bail!(
+                    "0x{:x} bytes from 0x{:x} go past the end of the ROM at 0x{:x}",
+                    len,
+                    start,
+                    Cartridge::LEN
+                ),
+            }
+        }
+        (None, None) => anyhow::bail!(
+            "Expected a length after the address, or one of gfx, gfx2, map, flags, music, sfx, or code"
+        ),
+    })
+}
+
+/// Print a region or address range of a ROM, in the same address space as `peek` in PICO-8.
+pub fn peek(
+    path: &Path,
+    start: &str,
+    len: Option<usize>,
+    encoding: Encoding,
+) -> anyhow::Result<()> {
+    let range = range(start, len)?;
+    let cartridge = Cartridge::read(path)?;
+    let bytes = cartridge.bytes(range.clone())?;
+    match encoding {
+        Encoding::Hex => {
+            for (i, row) in bytes.chunks(16).enumerate() {
+                let hex = row.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>();
+                println!("{:04x}: {}", range.start + i * 16, hex.join(" "));
+            }
+        }
+        Encoding::Bin => std::io::stdout().write_all(bytes)?,
+        Encoding::LuaString => println!("{}", p8scii::decode(&lua::string_literal(bytes))),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regions_are_named_like_the_dump_sections() {
+        assert_eq!(region("gfx"), Some(0..0x2000));
+        assert_eq!(region("gfx2"), Some(MAP_SHARED));
+        assert_eq!(region("map"), Some(0x2000..0x3000));
+        assert_eq!(region("flags"), Some(0x3000..0x3100));
+        assert_eq!(region("music"), Some(0x3100..0x3200));
+        assert_eq!(region("sfx"), Some(0x3200..0x4300));
+        assert_eq!(region("code"), Some(0x4300..0x8000));
+        assert_eq!(region("label"), None);
+    }
+
+    #[test]
+    fn numbers_are_decimal_or_hex() {
+        assert_eq!(parse_number("4352"), Ok(0x1100));
+        assert_eq!(parse_number("0x3100"), Ok(0x3100));
+        assert!(parse_number("0x").is_err());
+        assert!(parse_number("-1").is_err());
+        assert!(parse_number("sfx").is_err());
+    }
+
+    #[test]
+    fn ranges_must_be_inside_the_rom() {
+        assert_eq!(range("0x3200", Some(68)).unwrap(), 0x3200..0x3244);
+        assert_eq!(range("0x7ff0", Some(0x10)).unwrap(), 0x7ff0..0x8000);
+        assert_eq!(range("sfx", None).unwrap(), SFX);
+        assert!(range("0x7ff0", Some(0x11)).is_err());
+        assert!(range("0x10", Some(usize::MAX)).is_err());
+        assert!(range("sfx", Some(4)).is_err());
+        assert!(range("0x3200", None).is_err());
+    }
+}
@@ -0,0 +1,252 @@
+//! Python bindings, enabled by the `python` feature and built with maturin.
+
+use crate::cartridge::Cartridge;
+use crate::error::Error;
+use crate::gfx;
+use crate::music::Section as MusicSection;
+use crate::sfx::{Effect, Instrument, Pitch, Section as SfxSection};
+use numpy::ndarray::Array2;
+use numpy::{IntoPyArray, PyArray2, PyReadonlyArray2};
+use packed_struct::prelude::Integer;
+use pyo3::exceptions::{PyIndexError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use pythonize::{depythonize, pythonize};
+use std::path::PathBuf;
+
+impl From<Error> for PyErr {
+    fn from(e: Error) -> Self {
+        PyValueError::new_err(e.to_string())
+    }
+}
+
+fn value_error(e: impl ToString) -> PyErr {
+    PyValueError::new_err(e.to_string())
+}
+
+fn check_index(name: &str, index: usize, len: usize) -> PyResult<()> {
+    if index >= len {
+        return Err(PyIndexError::new_err(format!(
+            "{} must be 0–{}; {} is out of range",
+            name,
+            len - 1,
+            index
+        )));
+    }
+    Ok(())
+}
+
+/// A PICO-8 cartridge. Sections are returned as dicts and lists in the same form as `dump --format json`,
+/// and the sprite sheet as a 128×128 NumPy array of colors.
+#[pyclass(name = "Cartridge")]
+struct PyCartridge {
+    cartridge: Cartridge,
+}
+
+#[pymethods]
+impl PyCartridge {
+    /// Read a `.p8.rom`, `.p8`, or `.p8.png` file, in the format given by its extension.
+    #[staticmethod]
+    fn load(path: PathBuf) -> PyResult<Self> {
+        let cartridge = Cartridge::read(&path).map_err(value_error)?;
+        Ok(PyCartridge { cartridge })
+    }
+
+    #[staticmethod]
+    fn from_bytes(rom: &[u8]) -> PyResult<Self> {
+        Ok(PyCartridge {
+            cartridge: Cartridge::from_slice(rom)?,
+        })
+    }
+
+    /// Write a `.p8.rom`, `.p8`, or `.p8.png` file, in the format given by its extension.
+    /// ROMs don't have labels, so saving one leaves the label out.
+    fn save(&self, path: PathBuf) -> PyResult<()> {
+        self.cartridge.write(&path).map_err(value_error)
+    }
+
+    fn to_bytes<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, self.cartridge.as_bytes())
+    }
+
+    fn music<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        pythonize(py, &self.cartridge.music()?).map_err(value_error)
+    }
+
+    /// Replace the music section. Everything is checked before anything is changed.
+    fn set_music(&mut self, music: &Bound<'_, PyAny>) -> PyResult<()> {
+        let music = depythonize::<MusicSection>(music).map_err(value_error)?;
+        Ok(self.cartridge.set_music(&music)?)
+    }
+
+    fn sfx<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        pythonize(py, &self.cartridge.sfx()?).map_err(value_error)
+    }
+
+    /// Replace the SFX section. Everything is checked before anything is changed.
+    fn set_sfx(&mut self, sfx: &Bound<'_, PyAny>) -> PyResult<()> {
+        let sfx = depythonize::<SfxSection>(sfx).map_err(value_error)?;
+        if let Some((_, e)) = sfx.validate().into_iter().next() {
+            return Err(e.into());
+        }
+        Ok(self.cartridge.set_sfx(&sfx)?)
+    }
+
+    /// One note as a dict of `pitch`, `instrument`, `volume`, and `effect`.
+    fn note<'py>(&self, py: Python<'py>, sfx: usize, note: usize) -> PyResult<Bound<'py, PyAny>> {
+        check_index("SFX", sfx, 64)?;
+        check_index("Note", note, 32)?;
+        let section = self.cartridge.sfx()?;
+        pythonize(py, &section.sfxes[sfx].notes[note]).map_err(value_error)
+    }
+
+    /// Change some fields of one note. Instruments and effects are named as in `note()`.
+    /// SFX with notes need a speed, so set one with `set_speed()` first.
+    #[pyo3(signature = (sfx, note, *, pitch=None, instrument=None, volume=None, effect=None))]
+    fn set_note(
+        &mut self,
+        sfx: usize,
+        note: usize,
+        pitch: Option<u8>,
+        instrument: Option<&Bound<'_, PyAny>>,
+        volume: Option<u8>,
+        effect: Option<&Bound<'_, PyAny>>,
+    ) -> PyResult<()> {
+        check_index("SFX", sfx, 64)?;
+        check_index("Note", note, 32)?;
+        let pitch = pitch.map(Pitch::try_from).transpose()?;
+        let instrument = instrument
+            .map(depythonize::<Instrument>)
+            .transpose()
+            .map_err(value_error)?;
+        if let Some(volume) = volume.filter(|v| *v > 7) {
+            return Err(Error::Volume(volume).into());
+        }
+        let effect = effect
+            .map(depythonize::<Effect>)
+            .transpose()
+            .map_err(value_error)?;
+
+        let mut section = self.cartridge.sfx()?;
+        let target = &mut section.sfxes[sfx].notes[note];
+        if let Some(pitch) = pitch {
+            target.set_pitch(pitch);
+        }
+        if let Some(instrument) = instrument {
+            target.set_instrument(instrument);
+        }
+        if let Some(volume) = volume {
+            target.set_volume(Integer::from(volume));
+        }
+        if let Some(effect) = effect {
+            target.set_effect(effect);
+        }
+        if let Some(e) = section.sfxes[sfx].validate().into_iter().next() {
+            return Err(e.into());
+        }
+        Ok(self.cartridge.set_sfx(&section)?)
+    }
+
+    fn set_speed(&mut self, sfx: usize, speed: u8) -> PyResult<()> {
+        check_index("SFX", sfx, 64)?;
+        let mut section = self.cartridge.sfx()?;
+        section.sfxes[sfx].speed = speed;
+        if let Some(e) = section.sfxes[sfx].validate().into_iter().next() {
+            return Err(e.into());
+        }
+        Ok(self.cartridge.set_sfx(&section)?)
+    }
+
+    fn set_loop(&mut self, sfx: usize, start: u8, end: u8) -> PyResult<()> {
+        check_index("SFX", sfx, 64)?;
+        let mut section = self.cartridge.sfx()?;
+        section.sfxes[sfx].loop_start = start;
+        section.sfxes[sfx].loop_end = end;
+        if let Some(e) = section.sfxes[sfx].validate().into_iter().next() {
+            return Err(e.into());
+        }
+        Ok(self.cartridge.set_sfx(&section)?)
+    }
+
+    fn gfx<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyArray2<u8>>> {
+        let pixels = gfx::sprite_sheet(&self.cartridge)?;
+        let array = Array2::from_shape_vec((gfx::SHEET_SIZE, gfx::SHEET_SIZE), pixels)
+            .map_err(value_error)?;
+        Ok(array.into_pyarray(py))
+    }
+
+    fn set_gfx(&mut self, pixels: PyReadonlyArray2<'_, u8>) -> PyResult<()> {
+        let pixels = pixels.as_array();
+        if pixels.shape() != [gfx::SHEET_SIZE, gfx::SHEET_SIZE] {
+            return Err(value_error(format!(
+                "The sprite sheet must be {0}×{0}",
+                gfx::SHEET_SIZE
+            )));
+        }
+        let pixels = pixels.iter().copied().collect::<Vec<_>>();
+        Ok(gfx::set_sprite_sheet(&mut self.cartridge, &pixels)?)
+    }
+}
+
+#[pymodule]
+fn pico8_rom(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyCartridge>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::PyDict;
+
+    /// Drive the module from Python the way the README does, without the NumPy parts.
+    #[test]
+    fn python_can_edit_and_save_carts() {
+        let path =
+            std::env::temp_dir().join(format!("pico8_rom_tool_python_{}.p8", std::process::id()));
+        Python::initialize();
+        Python::attach(|py| -> PyResult<()> {
+            let module = PyModule::new(py, "pico8_rom")?;
+            pico8_rom(&module)?;
+            let locals = PyDict::new(py);
+            locals.set_item("pico8_rom", module)?;
+            locals.set_item("path", &path)?;
+            py.run(
+                cr#"
+cart = pico8_rom.Cartridge.from_bytes(bytes(0x8000))
+try:
+    cart.set_note(0, 3, pitch=24, volume=5)
+    raise AssertionError("a note was added to an SFX with speed 0")
+except ValueError:
+    pass
+cart.set_speed(0, 12)
+cart.set_note(0, 3, pitch=24, instrument="saw", volume=5, effect="fade_out")
+try:
+    cart.set_speed(0, 0)
+    raise AssertionError("speed 0 was accepted for an SFX with notes")
+except ValueError:
+    pass
+cart.save(path)
+loaded = pico8_rom.Cartridge.load(path)
+assert loaded.sfx() == cart.sfx()
+assert loaded.note(0, 3) == cart.note(0, 3)
+assert loaded.note(0, 3)["volume"] == 5
+assert loaded.sfx()["sfxes"][0]["speed"] == 12
+try:
+    loaded.set_loop(0, 40, 0)
+    raise AssertionError("a loop start of 40 was accepted")
+except ValueError:
+    pass
+try:
+    loaded.note(64, 0)
+    raise AssertionError("SFX 64 was accepted")
+except IndexError:
+    pass
+"#,
+                None,
+                Some(&locals),
+            )
+        })
+        .unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,249 @@
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// Machine-readable summary of a `translate` run.
+#[derive(Serialize, Debug, Default)]
+pub struct Report {
+    pub sfx: Vec<Entry>,
+    pub patterns: Vec<Entry>,
+}
+
+impl Report {
+    /// Records the outcome of mapping an SFX.
+    pub fn record_sfx<T>(&mut self, index: usize, result: &Result<Mapped<T>, Rejection>) {
+        Self::record(&mut self.sfx, index, result);
+    }
+
+    /// Records the outcome of mapping a music pattern.
+    pub fn record_pattern<T>(&mut self, index: usize, result: &Result<Mapped<T>, Rejection>) {
+        Self::record(&mut self.patterns, index, result);
+    }
+
+    fn record<T>(entries: &mut Vec<Entry>, index: usize, result: &Result<Mapped<T>, Rejection>) {
+        let outcome = match result {
+            Ok(mapped) => mapped.outcome(),
+            Err(rejection) => rejection.outcome(),
+        };
+        entries.push(Entry { index, outcome });
+    }
+
+    /// A line for every approximation and every rejection, SFX first.
+    pub fn messages(&self) -> Vec<String> {
+        let mut messages = vec![];
+        for (kind, entries) in [("SFX", &self.sfx), ("pattern", &self.patterns)] {
+            for entry in entries.iter() {
+                match &entry.outcome {
+                    Outcome::Translated => (),
+                    Outcome::Approximated { approximations } => {
+                        for approximation in approximations.iter() {
+                            messages.push(format!(
+                                "Approximating {} {}: {}",
+                                kind, entry.index, approximation
+                            ));
+                        }
+                    }
+                    Outcome::Rejected { reasons } => messages.push(format!(
+                        "Skipping {} {}: {}",
+                        kind,
+                        entry.index,
+                        reasons.join("; ")
+                    )),
+                }
+            }
+        }
+        messages
+    }
+
+    pub fn write(&self, path: &Path) -> crate::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+}
+
+/// Outcome of translating a single PICO-8 SFX or music pattern.
+#[derive(Serialize, Debug)]
+pub struct Entry {
+    pub index: usize,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Outcome {
+    Translated,
+    Approximated { approximations: Vec<String> },
+    Rejected { reasons: Vec<String> },
+}
+
+/// A successful mapping, possibly with some features approximated.
+#[derive(Debug)]
+pub struct Mapped<T> {
+    pub value: T,
+    pub approximations: Vec<String>,
+}
+
+impl<T> Mapped<T> {
+    pub fn outcome(&self) -> Outcome {
+        if self.approximations.is_empty() {
+            Outcome::Translated
+        } else {
+            Outcome::Approximated {
+                approximations: self.approximations.clone(),
+            }
+        }
+    }
+}
+
+/// Every reason a PICO-8 SFX or pattern couldn't be mapped.
+#[derive(Debug)]
+pub struct Rejection {
+    pub reasons: Vec<String>,
+}
+
+impl Rejection {
+    pub fn outcome(&self) -> Outcome {
+        Outcome::Rejected {
+            reasons: self.reasons.clone(),
+        }
+    }
+}
+
+impl From<&str> for Rejection {
+    fn from(reason: &str) -> Self {
+        Rejection {
+            reasons: vec![reason.to_string()],
+        }
+    }
+}
+
+impl From<Reasons> for Rejection {
+    fn from(reasons: Reasons) -> Self {
+        Rejection {
+            reasons: reasons.into_vec(),
+        }
+    }
+}
+
+impl Display for Rejection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.reasons.join("; "))
+    }
+}
+
+/// Collects reasons without repeating any, since the same problem often shows up on every note.
+#[derive(Debug, Default)]
+pub struct Reasons(Vec<String>);
+
+impl Reasons {
+    pub fn push(&mut self, reason: String) {
+        if !self.0.contains(&reason) {
+            self.0.push(reason);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn into_vec(self) -> Vec<String> {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sfx::Instrument;
+    use crate::translate::tests::{cart, note, pattern, sfx};
+    use crate::translate::{translate, Target};
+
+    /// A square SFX that WASM-4 can play, a saw SFX that it can't, patterns that use each,
+    /// and a pattern that plays the square SFX on two channels, which share one WASM-4 channel.
+    fn report() -> Report {
+        let cartridge = cart(
+            &[
+                sfx(16, &[note(24, Instrument::Square, 5)]),
+                sfx(16, &[note(24, Instrument::Saw, 5)]),
+            ],
+            &[pattern(&[0]), pattern(&[1]), pattern(&[0, 0])],
+        );
+        let mut report = Report::default();
+        translate(&cartridge, Target::Wasm4, &mut report).unwrap();
+        report
+    }
+
+    #[test]
+    fn every_sfx_and_pattern_gets_an_entry() {
+        let report = report();
+        assert_eq!(64, report.sfx.len());
+        assert_eq!(64, report.patterns.len());
+        assert!(matches!(report.sfx[0].outcome, Outcome::Translated));
+        assert!(matches!(
+            &report.sfx[1].outcome,
+            Outcome::Rejected { reasons } if reasons == &["Unsupported instrument: Saw"]
+        ));
+        assert!(matches!(report.patterns[0].outcome, Outcome::Translated));
+        assert!(matches!(
+            report.patterns[1].outcome,
+            Outcome::Rejected { .. }
+        ));
+        assert!(matches!(
+            report.patterns[2].outcome,
+            Outcome::Approximated { .. }
+        ));
+        assert!(matches!(
+            report.patterns[3].outcome,
+            Outcome::Rejected { .. }
+        ));
+        let messages = report.messages();
+        assert_eq!("Skipping SFX 1: Unsupported instrument: Saw", messages[0]);
+        assert!(messages.contains(
+            &"Skipping pattern 1: Channel 0 plays SFX 1, which was not translated".to_string()
+        ));
+        assert!(messages.contains(
+            &"Approximating pattern 2: Channels 0 and 1 both use the WASM-4 pulse 2 channel"
+                .to_string()
+        ));
+    }
+
+    #[test]
+    fn report_json_has_a_status_per_entry() {
+        let json = serde_json::to_value(report()).unwrap();
+        assert_eq!(
+            serde_json::json!({"index": 0, "status": "translated"}),
+            json["sfx"][0]
+        );
+        assert_eq!(
+            serde_json::json!({
+                "index": 1,
+                "status": "rejected",
+                "reasons": ["Unsupported instrument: Saw"],
+            }),
+            json["sfx"][1]
+        );
+        assert_eq!(
+            serde_json::json!({
+                "index": 2,
+                "status": "approximated",
+                "approximations": [
+                    "Channels 0 and 1 both use the WASM-4 pulse 2 channel",
+                    "Channels 0 and 1 both use the WASM-4 triangle channel",
+                ],
+            }),
+            json["patterns"][2]
+        );
+        assert_eq!(
+            serde_json::json!({
+                "index": 3,
+                "status": "rejected",
+                "reasons": ["No channels in this pattern"],
+            }),
+            json["patterns"][3]
+        );
+    }
+}
@@ -1,29 +1,67 @@
-use anyhow;
+use crate::cartridge::Cartridge;
+use crate::error::Result;
 use packed_struct::types::bits::ByteArray;
 use packed_struct::PackedStruct;
-use std::fmt::Debug;
-use std::fs::File;
 use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
 use std::path::Path;
 
-pub fn read_section<T: PackedStruct>(path: &Path, offset: u64) -> anyhow::Result<T> {
-    path.file_name()
-        .map(|x| x.to_string_lossy())
-        .map(|x| x.ends_with(".p8.rom"))
-        .filter(|x| *x)
-        .ok_or(anyhow::anyhow!(
-            "Not a supported PICO-8 ROM format: {}",
-            path.to_string_lossy()
-        ))?;
-    let mut rom = File::open(path)?;
-    rom.seek(SeekFrom::Start(offset))?;
+/// Sprite sheet. The bottom half is shared with the bottom half of the map.
+/// See https://pico-8.fandom.com/wiki/Memory#Sprite_sheet
+pub const GFX: Range<usize> = 0x0000..0x2000;
+/// Top half of the map, 128×32 cells of one sprite number each.
+/// See https://pico-8.fandom.com/wiki/Memory#Map
+pub const MAP: Range<usize> = 0x2000..0x3000;
+/// Bottom half of the map, rows 32–63, which is also the bottom half of the sprite sheet.
+pub const MAP_SHARED: Range<usize> = 0x1000..0x2000;
+/// One byte of flags per sprite.
+/// See https://pico-8.fandom.com/wiki/Memory#Sprite_flags
+pub const GFF: Range<usize> = 0x3000..0x3100;
+pub const MUSIC: Range<usize> = 0x3100..0x3200;
+pub const SFX: Range<usize> = 0x3200..0x4300;
+/// Compressed Lua source.
+/// See https://pico-8.fandom.com/wiki/P8PNGFileFormat#Compressed_format
+pub const CODE: Range<usize> = 0x4300..0x8000;
+
+/// Byte ranges of each row of 8 pixels in a sprite, relative to the start of the sprite sheet.
+pub fn sprite_rows(sprite: usize) -> impl Iterator<Item = Range<usize>> {
+    let (x, y) = (sprite % 16 * 4, sprite / 16 * 8);
+    (0..8).map(move |row| {
+        let start = (y + row) * 64 + x;
+        start..start + 4
+    })
+}
+
+/// Read one section of a ROM file.
+pub fn read_section<T: PackedStruct>(path: &Path, offset: u64) -> Result<T> {
+    Cartridge::read(path)?.section(offset as usize)
+}
+
+/// Read one section from a stream holding a ROM, without reading the rest of it.
+pub fn read_section_from<T: PackedStruct, R: Read + Seek>(
+    reader: &mut R,
+    offset: u64,
+) -> Result<T> {
+    reader.seek(SeekFrom::Start(offset))?;
     let mut buf = T::ByteArray::new(0);
-    rom.read_exact(buf.as_mut_bytes_slice())?;
-    T::unpack(&buf).map_err(|e| anyhow::anyhow!(e))
+    reader.read_exact(buf.as_mut_bytes_slice())?;
+    Ok(T::unpack(&buf)?)
+}
+
+/// Raw bytes of a part of the ROM that doesn't have a section type.
+pub fn read_bytes(path: &Path, offset: u64, len: usize) -> Result<Vec<u8>> {
+    let start = offset as usize;
+    Ok(Cartridge::read(path)?.bytes(start..start + len)?.to_vec())
 }
 
-pub fn dump_section<T: PackedStruct + Debug>(path: &Path, offset: u64) -> anyhow::Result<()> {
-    let section = read_section::<T>(path, offset)?;
-    println!("{:#?}", section);
-    Ok(())
+/// Copy a ROM to `output`, replacing the bytes of one section.
+pub fn write_section<T: PackedStruct>(
+    path: &Path,
+    output: &Path,
+    offset: u64,
+    section: &T,
+) -> Result<()> {
+    let mut cartridge = Cartridge::read(path)?;
+    cartridge.set_section(offset as usize, section)?;
+    cartridge.write(output)
 }
@@ -1,17 +1,13 @@
-use crate::rom;
-use anyhow;
+use crate::error::{Error, Result};
 use packed_struct::prelude::*;
 use packed_struct::PrimitiveEnum;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt::{Display, Formatter};
 use std::ops::RangeInclusive;
-use std::path::Path;
-
-pub fn dump(path: &Path) -> anyhow::Result<()> {
-    rom::dump_section::<Section>(path, 0x3200)
-}
 
 /// See https://pico-8.fandom.com/wiki/Memory#Sound_effects
-#[derive(PackedStruct, Debug)]
+#[derive(PackedStruct, Serialize, Deserialize, Clone, Debug)]
+#[serde(try_from = "SectionData", into = "SectionData")]
 pub struct Section {
     #[packed_field(element_size_bytes = "68")]
     pub sfxes: [Sfx; 64],
@@ -91,7 +87,8 @@ impl Default for Section {
     }
 }
 
-#[derive(PackedStruct, Debug, Default)]
+#[derive(PackedStruct, Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(try_from = "SfxData", into = "SfxData")]
 pub struct Sfx {
     #[packed_field(element_size_bytes = "2")]
     pub notes: [Note; 32],
@@ -102,16 +99,43 @@ pub struct Sfx {
     pub loop_end: u8,
 }
 
+impl Section {
+    /// Every problem with every SFX, by SFX index.
+    pub fn validate(&self) -> Vec<(usize, Error)> {
+        self.sfxes
+            .iter()
+            .enumerate()
+            .flat_map(|(i, sfx)| sfx.validate().into_iter().map(move |e| (i, e)))
+            .collect()
+    }
+}
+
 impl Sfx {
     pub fn enabled(&self) -> bool {
-        self.notes
-            .iter()
-            .fold(false, |acc, x| acc || u8::from(x.volume()) > 0)
+        self.notes.iter().any(|x| u8::from(x.volume()) > 0)
+    }
+
+    /// Problems that would make this SFX decode or play incorrectly.
+    pub fn validate(&self) -> Vec<Error> {
+        let mut errors = vec![];
+        if let Err(e) = self.switches.validate() {
+            errors.push(e);
+        }
+        for (name, value) in [("loop start", self.loop_start), ("loop end", self.loop_end)] {
+            if value > 32 {
+                errors.push(Error::LoopPoint { name, value });
+            }
+        }
+        if self.speed == 0 && self.enabled() {
+            errors.push(Error::Speed);
+        }
+        errors
     }
 }
 
-#[derive(PackedStruct, Debug, Default)]
+#[derive(PackedStruct, Serialize, Deserialize, Clone, Debug, Default)]
 #[packed_struct(size_bytes = "1", bit_numbering = "lsb0")]
+#[serde(try_from = "SwitchesData", into = "SwitchesData")]
 pub struct Switches {
     #[packed_field(bits = "0")]
     pub tracker_mode: bool,
@@ -124,44 +148,77 @@ pub struct Switches {
 }
 
 impl Switches {
+    /// Detune, reverb, and dampen are packed in base 3, so values from 27 up don't mean anything.
+    pub fn validate(&self) -> Result<()> {
+        match u8::from(self.other_filters) {
+            val if val >= 27 => Err(Error::PackedFilters(val)),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_filter(filter: &'static str, value: u8) -> Result<()> {
+        if value > 2 {
+            return Err(Error::Filter { filter, value });
+        }
+        Ok(())
+    }
+
+    /// Names of the filters that are switched on, with levels for the ones that have them.
+    pub fn filters(&self) -> Vec<String> {
+        let mut filters = vec![];
+        for (enabled, name) in [(self.noiz, "noiz"), (self.buzz, "buzz")] {
+            if enabled {
+                filters.push(name.to_string());
+            }
+        }
+        for (level, name) in [
+            (self.detune(), "detune"),
+            (self.reverb(), "reverb"),
+            (self.dampen(), "dampen"),
+        ] {
+            if level != 0 {
+                filters.push(format!("{} {}", name, level));
+            }
+        }
+        filters
+    }
+
     pub fn detune(&self) -> u8 {
         u8::from(self.other_filters) % 3
     }
 
-    pub fn set_detune(&mut self, val: u8) {
-        if val > 2 {
-            panic!("Detune must be 0–2; {} is out of range", val);
-        }
+    pub fn set_detune(&mut self, val: u8) -> Result<()> {
+        Self::check_filter("Detune", val)?;
         self.other_filters = Integer::from(val + self.reverb() * 3 + self.dampen() * 9);
+        Ok(())
     }
 
     pub fn reverb(&self) -> u8 {
         (u8::from(self.other_filters) / 3) % 3
     }
 
-    pub fn set_reverb(&mut self, val: u8) {
-        if val > 2 {
-            panic!("Reverb must be 0–2; {} is out of range", val);
-        }
+    pub fn set_reverb(&mut self, val: u8) -> Result<()> {
+        Self::check_filter("Reverb", val)?;
         self.other_filters = Integer::from(self.detune() + val * 3 + self.dampen() * 9);
+        Ok(())
     }
 
     pub fn dampen(&self) -> u8 {
         (u8::from(self.other_filters) / 9) % 3
     }
 
-    pub fn set_dampen(&mut self, val: u8) {
-        if val > 2 {
-            panic!("Dampen must be 0–2; {} is out of range", val);
-        }
+    pub fn set_dampen(&mut self, val: u8) -> Result<()> {
+        Self::check_filter("Dampen", val)?;
         self.other_filters = Integer::from(self.detune() + self.reverb() * 3 + val * 9);
+        Ok(())
     }
 }
 
 /// See https://www.lexaloffle.com/dl/docs/pico-8_manual.html#SFX_Editor
 /// All the accessors are workarounds for https://github.com/hashmismatch/packed_struct.rs/issues/92
-#[derive(PackedStruct, Debug, Default)]
+#[derive(PackedStruct, Serialize, Deserialize, Clone, Debug, Default)]
 #[packed_struct(size_bytes = "2", endian = "lsb")]
+#[serde(try_from = "NoteData", into = "NoteData")]
 pub struct Note {
     packed: u16,
 }
@@ -180,7 +237,8 @@ impl Note {
     }
 
     fn write(&mut self, bits: &RangeInclusive<u8>, val: u8) {
-        self.packed |= Self::mask(bits) & ((val as u16) << Self::shift(bits))
+        let mask = Self::mask(bits);
+        self.packed = (self.packed & !mask) | (mask & ((val as u16) << Self::shift(bits)))
     }
 
     const PITCH_BITS: RangeInclusive<u8> = 0..=5;
@@ -190,7 +248,8 @@ impl Note {
     const SFX_INSTRUMENT_BITS: RangeInclusive<u8> = 15..=15;
 
     pub fn pitch(&self) -> Pitch {
-        Pitch::from(self.read(&Self::PITCH_BITS))
+        // 6 bits can't hold anything out of range.
+        Pitch(self.read(&Self::PITCH_BITS))
     }
 
     pub fn set_pitch(&mut self, val: Pitch) {
@@ -206,7 +265,7 @@ impl Note {
     }
 
     pub fn effect(&self) -> Effect {
-        Effect::from_primitive(self.read(&Self::EFFECT_BITS)).expect("Impossible effect number")
+        Effect::ALL[self.read(&Self::EFFECT_BITS) as usize]
     }
 
     pub fn set_effect(&mut self, val: Effect) {
@@ -219,17 +278,7 @@ impl Note {
         if sfx_instrument {
             Instrument::Sfx(Integer::from(waveform))
         } else {
-            match waveform {
-                0 => Instrument::Triangle,
-                1 => Instrument::TiltedSaw,
-                2 => Instrument::Saw,
-                3 => Instrument::Square,
-                4 => Instrument::Pulse,
-                5 => Instrument::Organ,
-                6 => Instrument::Noise,
-                7 => Instrument::Phaser,
-                waveform => panic!("Impossible waveform number: {}", waveform),
-            }
+            Instrument::WAVEFORMS[waveform as usize]
         }
     }
 
@@ -240,21 +289,39 @@ impl Note {
         };
         self.write(&Self::SFX_INSTRUMENT_BITS, sfx_instrument);
         let waveform = match val {
-            Instrument::Sfx(waveform) => u8::from(waveform),
-            Instrument::Triangle => 0,
-            Instrument::TiltedSaw => 1,
-            Instrument::Saw => 2,
-            Instrument::Square => 3,
-            Instrument::Pulse => 4,
-            Instrument::Organ => 5,
-            Instrument::Noise => 6,
-            Instrument::Phaser => 7,
+            Instrument::Sfx(sfx) => u8::from(sfx),
+            instrument => instrument.waveform().unwrap_or_default(),
         };
         self.write(&Self::WAVEFORM_BITS, waveform);
     }
 }
 
-#[derive(Debug, Default)]
+impl Note {
+    /// Note and PICO-8 octave, instrument, volume, and effect, like `C#2 3 5 1` in the PICO-8 tracker.
+    /// SFX instruments are shown with an `s` before their number, and silent notes as dots.
+    pub fn tracker_row(&self) -> String {
+        let volume = u8::from(self.volume());
+        if volume == 0 {
+            return "... . . .".to_string();
+        }
+        let pitch = self.pitch();
+        let instrument = match self.instrument() {
+            Instrument::Sfx(sfx) => format!("s{}", u8::from(sfx)),
+            instrument => instrument.waveform().unwrap_or_default().to_string(),
+        };
+        format!(
+            "{:-<2}{} {} {} {}",
+            pitch.name().replace('♯', "#"),
+            pitch.octave(),
+            instrument,
+            volume,
+            self.effect().to_primitive()
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
 pub enum Instrument {
     #[default]
     Triangle,
@@ -265,11 +332,55 @@ pub enum Instrument {
     Organ,
     Noise,
     Phaser,
-    Sfx(Integer<u8, packed_bits::Bits<3>>),
+    Sfx(#[serde(with = "sfx_instrument")] Integer<u8, packed_bits::Bits<3>>),
+}
+
+impl Instrument {
+    /// Built-in instruments, by waveform number.
+    const WAVEFORMS: [Instrument; 8] = [
+        Instrument::Triangle,
+        Instrument::TiltedSaw,
+        Instrument::Saw,
+        Instrument::Square,
+        Instrument::Pulse,
+        Instrument::Organ,
+        Instrument::Noise,
+        Instrument::Phaser,
+    ];
+
+    /// Waveform number of a built-in instrument, or `None` for an SFX instrument.
+    pub fn waveform(&self) -> Option<u8> {
+        Self::WAVEFORMS
+            .iter()
+            .position(|x| x == self)
+            .map(|i| i as u8)
+    }
+}
+
+impl Display for Instrument {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Instrument::Sfx(sfx) => write!(f, "SFX instrument {}", u8::from(*sfx)),
+            instrument => write!(f, "{:?}", instrument),
+        }
+    }
+}
+
+/// Built-in instrument from its waveform number.
+impl TryFrom<u8> for Instrument {
+    type Error = Error;
+
+    fn try_from(val: u8) -> Result<Self> {
+        Self::WAVEFORMS
+            .get(val as usize)
+            .copied()
+            .ok_or(Error::Waveform(val))
+    }
 }
 
 /// See https://www.lexaloffle.com/dl/docs/pico-8_manual.html#Effects
-#[derive(PrimitiveEnum_u8, Clone, Copy, Debug, Default, PartialEq)]
+#[derive(PrimitiveEnum_u8, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
 pub enum Effect {
     #[default]
     None = 0,
@@ -282,6 +393,29 @@ pub enum Effect {
     ArpeggioSlow = 7,
 }
 
+impl Effect {
+    const ALL: [Effect; 8] = [
+        Effect::None,
+        Effect::Slide,
+        Effect::Vibrato,
+        Effect::Drop,
+        Effect::FadeIn,
+        Effect::FadeOut,
+        Effect::ArpeggioFast,
+        Effect::ArpeggioSlow,
+    ];
+}
+
+impl TryFrom<u8> for Effect {
+    type Error = Error;
+
+    fn try_from(val: u8) -> Result<Self> {
+        Effect::from_primitive(val).ok_or(Error::Effect(val))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(try_from = "u8", into = "u8")]
 pub struct Pitch(u8);
 
 /// MIDI note number and frequency assume MIDI Tuning Standard (12-tone equal temperament, middle C is C4).
@@ -308,12 +442,14 @@ impl Pitch {
     }
 }
 
-impl From<u8> for Pitch {
-    fn from(val: u8) -> Self {
+impl TryFrom<u8> for Pitch {
+    type Error = Error;
+
+    fn try_from(val: u8) -> Result<Self> {
         if val > 63 {
-            panic!("PICO-8 cannot represent notes above D♯5");
+            return Err(Error::Pitch(val));
         }
-        Pitch(val)
+        Ok(Pitch(val))
     }
 }
 
@@ -335,3 +471,323 @@ impl Display for Pitch {
         )
     }
 }
+
+// Serialized forms use plain numbers and named flags instead of packed fields.
+
+#[derive(Serialize, Deserialize)]
+struct SectionData {
+    sfxes: Vec<Sfx>,
+}
+
+impl From<Section> for SectionData {
+    fn from(section: Section) -> Self {
+        SectionData {
+            sfxes: section.sfxes.to_vec(),
+        }
+    }
+}
+
+impl TryFrom<SectionData> for Section {
+    type Error = Error;
+
+    fn try_from(data: SectionData) -> Result<Self> {
+        let actual = data.sfxes.len();
+        Ok(Section {
+            sfxes: data.sfxes.try_into().map_err(|_| Error::Count {
+                name: "SFX",
+                expected: 64,
+                actual,
+            })?,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SfxData {
+    notes: Vec<Note>,
+    switches: Switches,
+    speed: u8,
+    loop_start: u8,
+    loop_end: u8,
+}
+
+impl From<Sfx> for SfxData {
+    fn from(sfx: Sfx) -> Self {
+        SfxData {
+            notes: sfx.notes.to_vec(),
+            switches: sfx.switches,
+            speed: sfx.speed,
+            loop_start: sfx.loop_start,
+            loop_end: sfx.loop_end,
+        }
+    }
+}
+
+impl TryFrom<SfxData> for Sfx {
+    type Error = Error;
+
+    fn try_from(data: SfxData) -> Result<Self> {
+        let actual = data.notes.len();
+        Ok(Sfx {
+            notes: data.notes.try_into().map_err(|_| Error::Count {
+                name: "notes",
+                expected: 32,
+                actual,
+            })?,
+            switches: data.switches,
+            speed: data.speed,
+            loop_start: data.loop_start,
+            loop_end: data.loop_end,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SwitchesData {
+    tracker_mode: bool,
+    noiz: bool,
+    buzz: bool,
+    detune: u8,
+    reverb: u8,
+    dampen: u8,
+}
+
+impl From<Switches> for SwitchesData {
+    fn from(switches: Switches) -> Self {
+        SwitchesData {
+            tracker_mode: switches.tracker_mode,
+            noiz: switches.noiz,
+            buzz: switches.buzz,
+            detune: switches.detune(),
+            reverb: switches.reverb(),
+            dampen: switches.dampen(),
+        }
+    }
+}
+
+impl TryFrom<SwitchesData> for Switches {
+    type Error = Error;
+
+    fn try_from(data: SwitchesData) -> Result<Self> {
+        let mut switches = Switches {
+            tracker_mode: data.tracker_mode,
+            noiz: data.noiz,
+            buzz: data.buzz,
+            ..Switches::default()
+        };
+        switches.set_detune(data.detune)?;
+        switches.set_reverb(data.reverb)?;
+        switches.set_dampen(data.dampen)?;
+        Ok(switches)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct NoteData {
+    pitch: Pitch,
+    instrument: Instrument,
+    volume: u8,
+    effect: Effect,
+}
+
+impl From<Note> for NoteData {
+    fn from(note: Note) -> Self {
+        NoteData {
+            pitch: note.pitch(),
+            instrument: note.instrument(),
+            volume: u8::from(note.volume()),
+            effect: note.effect(),
+        }
+    }
+}
+
+impl TryFrom<NoteData> for Note {
+    type Error = Error;
+
+    fn try_from(data: NoteData) -> Result<Self> {
+        if data.volume > 7 {
+            return Err(Error::Volume(data.volume));
+        }
+        let mut note = Note::default();
+        note.set_pitch(data.pitch);
+        note.set_instrument(data.instrument);
+        note.set_volume(Integer::from(data.volume));
+        note.set_effect(data.effect);
+        Ok(note)
+    }
+}
+
+/// SFX instruments are serialized as their SFX number, 0–7.
+mod sfx_instrument {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        val: &Integer<u8, packed_bits::Bits<3>>,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        u8::from(*val).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Integer<u8, packed_bits::Bits<3>>, D::Error> {
+        match u8::deserialize(deserializer)? {
+            val if val > 7 => Err(serde::de::Error::custom(Error::Waveform(val))),
+            val => Ok(Integer::from(val)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every possible packed note.
+    fn notes() -> impl Iterator<Item = Note> {
+        (0..=u16::MAX).map(|packed| Note { packed })
+    }
+
+    fn instruments() -> Vec<Instrument> {
+        let mut instruments = Instrument::WAVEFORMS.to_vec();
+        instruments.extend((0..8).map(|i| Instrument::Sfx(Integer::from(i))));
+        instruments
+    }
+
+    fn effects() -> Vec<Effect> {
+        Effect::ALL.to_vec()
+    }
+
+    #[test]
+    fn setting_current_value_changes_nothing() {
+        for note in notes() {
+            let packed = note.packed;
+            let mut copy = Note { packed };
+            copy.set_pitch(note.pitch());
+            copy.set_instrument(note.instrument());
+            copy.set_volume(note.volume());
+            copy.set_effect(note.effect());
+            assert_eq!(packed, copy.packed);
+        }
+    }
+
+    #[test]
+    fn pitch_round_trips() {
+        for mut note in notes() {
+            let (instrument, volume, effect) = (note.instrument(), note.volume(), note.effect());
+            for pitch in 0..=63 {
+                note.set_pitch(Pitch::try_from(pitch).unwrap());
+                assert_eq!(pitch, u8::from(note.pitch()));
+                assert_eq!(instrument, note.instrument());
+                assert_eq!(volume, note.volume());
+                assert_eq!(effect, note.effect());
+            }
+        }
+    }
+
+    #[test]
+    fn instrument_round_trips() {
+        let instruments = instruments();
+        for mut note in notes() {
+            let (pitch, volume, effect) = (note.pitch(), note.volume(), note.effect());
+            for instrument in instruments.iter() {
+                note.set_instrument(*instrument);
+                assert_eq!(*instrument, note.instrument());
+                assert_eq!(pitch, note.pitch());
+                assert_eq!(volume, note.volume());
+                assert_eq!(effect, note.effect());
+            }
+        }
+    }
+
+    #[test]
+    fn waveform_numbers_match_the_instruments() {
+        for waveform in 0..8 {
+            let instrument = Instrument::try_from(waveform).unwrap();
+            assert_eq!(Some(waveform), instrument.waveform());
+        }
+        assert_eq!(None, Instrument::Sfx(Integer::from(3)).waveform());
+    }
+
+    #[test]
+    fn volume_round_trips() {
+        for mut note in notes() {
+            let (pitch, instrument, effect) = (note.pitch(), note.instrument(), note.effect());
+            for volume in 0..=7 {
+                note.set_volume(Integer::from(volume));
+                assert_eq!(volume, u8::from(note.volume()));
+                assert_eq!(pitch, note.pitch());
+                assert_eq!(instrument, note.instrument());
+                assert_eq!(effect, note.effect());
+            }
+        }
+    }
+
+    #[test]
+    fn effect_round_trips() {
+        let effects = effects();
+        for mut note in notes() {
+            let (pitch, instrument, volume) = (note.pitch(), note.instrument(), note.volume());
+            for effect in effects.iter() {
+                note.set_effect(*effect);
+                assert_eq!(*effect, note.effect());
+                assert_eq!(pitch, note.pitch());
+                assert_eq!(instrument, note.instrument());
+                assert_eq!(volume, note.volume());
+            }
+        }
+    }
+
+    #[test]
+    fn out_of_range_values_are_errors() {
+        assert!(matches!(Pitch::try_from(64), Err(Error::Pitch(64))));
+        assert!(matches!(Effect::try_from(8), Err(Error::Effect(8))));
+        assert!(matches!(Instrument::try_from(8), Err(Error::Waveform(8))));
+        let mut switches = Switches::default();
+        assert!(switches.set_reverb(3).is_err());
+        assert_eq!(0, switches.reverb());
+        switches.other_filters = Integer::from(27);
+        assert!(matches!(switches.validate(), Err(Error::PackedFilters(27))));
+    }
+
+    #[test]
+    fn tracker_rows_show_note_instrument_volume_and_effect() {
+        let row = |pitch: u8, instrument: Instrument, volume: u8, effect: Effect| {
+            let mut note = Note::default();
+            note.set_pitch(Pitch::try_from(pitch).unwrap());
+            note.set_instrument(instrument);
+            note.set_volume(Integer::from(volume));
+            note.set_effect(effect);
+            note.tracker_row()
+        };
+        assert_eq!("C-0 0 1 0", row(0, Instrument::Triangle, 1, Effect::None));
+        assert_eq!("C#2 2 5 3", row(25, Instrument::Saw, 5, Effect::Drop));
+        assert_eq!(
+            "B-3 7 7 7",
+            row(47, Instrument::Phaser, 7, Effect::ArpeggioSlow)
+        );
+        assert_eq!(
+            "D#5 s6 4 1",
+            row(63, Instrument::Sfx(Integer::from(6)), 4, Effect::Slide)
+        );
+        assert_eq!("... . . .", row(30, Instrument::Organ, 0, Effect::Vibrato));
+    }
+
+    #[test]
+    fn notes_round_trip_through_json() {
+        for note in notes() {
+            let json = serde_json::to_string(&note).unwrap();
+            let parsed: Note = serde_json::from_str(&json).unwrap();
+            assert_eq!(note.packed, parsed.packed, "{}", json);
+        }
+    }
+
+    #[test]
+    fn out_of_range_json_is_rejected() {
+        let note = r#"{"pitch": 12, "instrument": "saw", "volume": 8, "effect": "none"}"#;
+        assert!(serde_json::from_str::<Note>(note).is_err());
+        let note = r#"{"pitch": 64, "instrument": "saw", "volume": 7, "effect": "none"}"#;
+        assert!(serde_json::from_str::<Note>(note).is_err());
+        let note = r#"{"pitch": 12, "instrument": {"sfx": 8}, "volume": 7, "effect": "none"}"#;
+        assert!(serde_json::from_str::<Note>(note).is_err());
+    }
+}
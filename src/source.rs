@@ -0,0 +1,66 @@
+use pico8_rom::lua::{self, Stats};
+use pico8_rom::{p8scii, Cartridge};
+use std::path::Path;
+
+/// Rewrite a ROM's code, then write a ROM with the new code to `output` or print the code as Unicode,
+/// and report how the token, character, and compressed byte counts changed.
+pub fn rewrite(
+    path: &Path,
+    output: Option<&Path>,
+    rewrite: fn(&[u8]) -> pico8_rom::Result<Vec<u8>>,
+) -> anyhow::Result<()> {
+    let mut cartridge = Cartridge::read(path)?;
+    let code = cartridge.code()?;
+    let new_code = rewrite(&code)?;
+    let (before, after) = (Stats::of(&code)?, Stats::of(&new_code)?);
+    eprintln!("tokens: {} → {}", before.tokens, after.tokens);
+    eprintln!("chars: {} → {}", before.chars, after.chars);
+    eprintln!(
+        "compressed bytes: {} → {}",
+        before.compressed, after.compressed
+    );
+    match output {
+        Some(output) => {
+            cartridge.set_code(&new_code)?;
+            cartridge.write(output)?;
+        }
+        None => print!("{}", p8scii::decode(&new_code)),
+    }
+    Ok(())
+}
+
+/// Print the size of a ROM's code against PICO-8's limits, then each function's token count.
+pub fn stats(path: &Path) -> anyhow::Result<()> {
+    let code = Cartridge::read(path)?.code()?;
+    let stats = Stats::of(&code)?;
+    let limits = [
+        ("tokens", stats.tokens, Stats::MAX_TOKENS),
+        ("chars", stats.chars, Stats::MAX_CHARS),
+        ("compressed bytes", stats.compressed, Stats::MAX_COMPRESSED),
+    ];
+    for (name, value, max) in limits {
+        println!("{}: {}/{}", name, value, max);
+    }
+
+    let functions = lua::functions(&lua::tokenize(&code)?);
+    if !functions.is_empty() {
+        println!();
+        println!(" line  tokens  function");
+        for function in functions {
+            println!(
+                "{:>5}  {:>6}  {}",
+                function.line, function.tokens, function.name
+            );
+        }
+    }
+
+    let over = limits
+        .iter()
+        .filter(|(_, value, max)| value > max)
+        .map(|(name, _, _)| *name)
+        .collect::<Vec<_>>();
+    if !over.is_empty() {
+        anyhow::bail!("Code is over PICO-8's limit for {}", over.join(", "));
+    }
+    Ok(())
+}
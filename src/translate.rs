@@ -1,209 +1,312 @@
-use crate::music::Pattern;
+mod famitracker;
+mod hugedriver;
+mod tic80;
+mod wasm4;
+
+use crate::cartridge::Cartridge;
+use crate::error::Error;
 use crate::music::Section as MusicSection;
-use crate::rom;
+use crate::report::{Reasons, Rejection, Report};
+use crate::rom::MUSIC;
 use crate::sfx::Section as SfxSection;
-use crate::sfx::{Effect, Instrument, Sfx};
-use anyhow;
+use crate::sfx::{Instrument, Sfx};
 use packed_struct::prelude::*;
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// Fantasy console to translate PICO-8 music and sfx for.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Target {
+    #[default]
+    Wasm4,
+    Tic80,
+    /// hUGEDriver song for GBDK, for the Game Boy.
+    Hugedriver,
+    /// FamiTracker text export, for the NES.
+    Famitracker,
+}
 
-pub fn translate(path: &Path) -> anyhow::Result<()> {
-    let section = rom::read_section::<MusicSfx>(path, 0x3100)?;
+/// Parses the lowercase names used by `translate --target`, ignoring case.
+impl FromStr for Target {
+    type Err = Error;
 
-    let mut wasm4sfxes = Vec::with_capacity(section.sfx.sfxes.len());
-    for (i, sfx) in section.sfx.sfxes.iter().enumerate() {
-        match map_sfx(sfx) {
-            Ok(wasm4sfx) => wasm4sfxes.push(wasm4sfx),
-            Err(e) => eprintln!("Skipping SFX {}: {}", i, e),
+    fn from_str(name: &str) -> crate::Result<Target> {
+        match name.to_lowercase().as_str() {
+            "wasm4" => Ok(Target::Wasm4),
+            "tic80" => Ok(Target::Tic80),
+            "hugedriver" => Ok(Target::Hugedriver),
+            "famitracker" => Ok(Target::Famitracker),
+            _ => Err(Error::Target(name.to_string())),
         }
     }
+}
 
-    let mut wasm4patterns = Vec::with_capacity(section.music.patterns.len());
-    for (i, pattern) in section.music.patterns.iter().enumerate() {
-        match map_music(pattern) {
-            Ok(wasm4pattern) => wasm4patterns.push(wasm4pattern),
-            Err(e) => eprintln!("Skipping pattern {}: {}", i, e),
-        }
+/// Source code or data for the target, with what happened to each SFX and pattern recorded in `report`.
+pub fn translate(
+    cartridge: &Cartridge,
+    target: Target,
+    report: &mut Report,
+) -> crate::Result<String> {
+    let section = cartridge.section::<MusicSfx>(MUSIC.start)?;
+    let mut out = String::new();
+    match target {
+        Target::Wasm4 => wasm4::translate(&section, report, &mut out)?,
+        Target::Tic80 => tic80::translate(&section, report, &mut out)?,
+        Target::Hugedriver => hugedriver::translate(&section, report, &mut out)?,
+        Target::Famitracker => famitracker::translate(&section, report, &mut out)?,
     }
+    Ok(out)
+}
 
-    // TODO: figure out much better way to emit this code
-    println!("//region SFX and music data");
-    println!();
+// Section type aliases are necessary because PackedStruct can't handle qualified field types.
+/// The music and sfx sections are contiguous in a PICO-8 ROM.
+#[derive(PackedStruct, Debug)]
+pub struct MusicSfx {
+    #[packed_field(size_bytes = "256")]
+    music: MusicSection,
+    #[packed_field(size_bytes = "4352")]
+    sfx: SfxSection,
+}
 
-    println!("const SFX_DATA: &[Sfx] = &[");
-    for wasm4sfx in wasm4sfxes {
-        println!("    Sfx{{");
-        println!("        frames_per_tone: {},", wasm4sfx.frames_per_tone);
-        if let Some(loop_restart) = wasm4sfx.loop_restart {
-            println!("        loop_restart: Some({}),", loop_restart);
-        } else {
-            println!("        loop_restart: None,");
-        }
-        println!("        tones: &[");
-        for tone in wasm4sfx.tones {
-            println!("            Tone{{");
-            println!("                frequency: {},", tone.frequency);
-            println!("                duration: {},", tone.duration);
-            println!("                volume: {},", tone.volume);
-            println!("                flags: {},", tone.flags);
-            println!("            }},");
-        }
-        println!("        ],");
-        println!("    }},");
-    }
-    println!("];");
-    println!();
-
-    println!("const MUSIC_DATA: &[Pattern] = &[");
-    for wasm4pattern in wasm4patterns {
-        println!("    Pattern{{");
-        println!("        loop_start: {},", wasm4pattern.loop_start);
-        println!("        loop_back: {},", wasm4pattern.loop_back);
-        println!("        stop_at_end: {},", wasm4pattern.stop_at_end);
-        println!("        sfxes: &[");
-        for sfx_id in wasm4pattern.sfx_ids {
-            println!("            &SFX_DATA[{}],", sfx_id);
-        }
-        println!("        ],");
-        println!("    }},");
+/// Rejects SFX that none of the tracker targets can represent.
+fn check_sfx(sfx: &Sfx) -> Result<(), Rejection> {
+    if !sfx.enabled() {
+        return Err(Rejection::from("No notes in this SFX"));
     }
-    println!("];");
-    println!();
 
-    println!("//endregion SFX and music data");
+    let mut reasons = Reasons::default();
+    for e in sfx.validate() {
+        reasons.push(e.to_string());
+    }
+    for note in sfx.notes.iter() {
+        if let Instrument::Sfx(_) = note.instrument() {
+            reasons.push(format!("Unsupported instrument: {:?}", note.instrument()));
+        }
+    }
+    if !reasons.is_empty() {
+        return Err(Rejection::from(reasons));
+    }
     Ok(())
 }
 
-fn map_music(pattern: &Pattern) -> anyhow::Result<Wasm4Pattern> {
-    // TODO: should we skip empty patterns? Does PICO-8 actually play them?
-    if !pattern.enabled() {
-        anyhow::bail!("No channels in this pattern");
+/// Tracker targets don't have anything like PICO-8's SFX filters.
+fn ignore_filters(sfx: &Sfx, approximations: &mut Reasons) {
+    for (enabled, filter) in [
+        (sfx.switches.buzz, "buzz"),
+        (sfx.switches.noiz, "noiz"),
+        (sfx.switches.detune() != 0, "detune"),
+        (sfx.switches.reverb() != 0, "reverb"),
+        (sfx.switches.dampen() != 0, "dampen"),
+    ] {
+        if enabled {
+            approximations.push(format!("SFX filter ignored: {}", filter));
+        }
     }
-
-    // TODO: check for inter-channel instrument conflicts
-
-    Ok(Wasm4Pattern {
-        loop_start: pattern.loop_start(),
-        loop_back: pattern.loop_back(),
-        stop_at_end: pattern.stop_at_end(),
-        sfx_ids: pattern
-            .channels
-            .iter()
-            .map(|c| u8::from(c.sfx_id) as usize)
-            .collect(),
-    })
 }
 
-fn map_sfx(sfx: &Sfx) -> anyhow::Result<Wasm4Sfx> {
-    // TODO: we can't actually skip every silent SFX,
-    //  as they might be used by music as spacers.
-    //  But for now, skip them so we don't have empty SFXes everywhere.
-    if !sfx.enabled() {
-        anyhow::bail!("No notes in this SFX");
-    }
-
-    // Check preconditions for entire SFX.
-    let frames_per_tone = match sfx.speed {
-        0 => anyhow::bail!("PICO-8 speed 0 isn't representable in the PICO-8 tracker and you probably shouldn't use it"),
-        s if s % 2 != 0 => anyhow::bail!("Odd PICO-8 speeds map to non-integer numbers of WASM-4 frames, and cannot be represented"),
-        s => s / 2,
-    };
-    if sfx.switches.buzz {
-        anyhow::bail!("Unsupported SFX filter: buzz");
-    }
-    if sfx.switches.noiz {
-        anyhow::bail!("Unsupported SFX filter: noiz");
-    }
-    if sfx.switches.detune() != 0 {
-        anyhow::bail!("Unsupported SFX filter: detune");
-    }
-    if sfx.switches.reverb() != 0 {
-        anyhow::bail!("Unsupported SFX filter: reverb");
-    }
-    if sfx.switches.dampen() != 0 {
-        anyhow::bail!("Unsupported SFX filter: dampen");
-    }
-
-    // Get SFX size and optional loop restart point.
-    let (loop_restart, size) = match (sfx.loop_start, sfx.loop_end) {
-        (0, 0) => (None, sfx.notes.len()),
-        (size, 0) => (None, size as usize),
-        (loop_restart, size) => (Some(loop_restart as usize), size as usize),
-    };
-
-    // Check preconditions for representable notes.
-    for note in sfx.notes[..size].iter() {
-        match note.instrument() {
-            Instrument::Triangle => (),
-            Instrument::Pulse => (),
-            Instrument::Square => (),
-            Instrument::Noise => (),
-            instrument => anyhow::bail!("Unsupported instrument: {:#?}", instrument),
+/// Number of notes to play from an SFX. Tracker patterns can't loop or end early,
+/// so the notes are played once and the rest of the pattern is silent.
+fn played_length(sfx: &Sfx, approximations: &mut Reasons) -> usize {
+    match (sfx.loop_start, sfx.loop_end) {
+        (0, 0) => sfx.notes.len(),
+        (size, 0) => {
+            approximations.push(format!(
+                "SFX length of {} notes padded to {} rows",
+                size,
+                sfx.notes.len()
+            ));
+            (size as usize).min(sfx.notes.len())
         }
-        if note.effect() != Effect::None {
-            anyhow::bail!("Unsupported effect: {:#?}", note.effect());
+        (loop_start, loop_end) => {
+            approximations.push(format!(
+                "Loop from note {} to {} ignored",
+                loop_start, loop_end
+            ));
+            sfx.notes.len()
         }
     }
+}
 
-    let tones = sfx.notes[..size]
-        .iter()
-        .map(|note| Wasm4Tone {
-            // TODO: emulate drop effect using frequency sweep?
-            frequency: note.pitch().frequency(),
-            // TODO: emulate other filters/effects using ADSR params?
-            duration: frames_per_tone as u32,
-            volume: (u8::from(note.volume()) as u32) * 100 / 7,
-            // TODO: specify channel to use for pulse/square tones
-            flags: match note.instrument() {
-                Instrument::Triangle => 0b10,
-                // pulse channel 1, default duty cycle
-                Instrument::Pulse => 0b00,
-                // pulse channel 2, 50% duty cycle
-                Instrument::Square => 0b10_01,
-                Instrument::Noise => 0b11,
-                // We checked for this above.
-                instrument => panic!("Unsupported instrument: {:#?}", instrument),
-            },
-        })
-        .collect::<Vec<_>>();
-    Ok(Wasm4Sfx {
-        frames_per_tone,
-        loop_restart,
-        tones,
+/// PICO-8 waveform number for a built-in instrument.
+/// `check_sfx` rejects SFX instruments, so they shouldn't get this far.
+fn waveform(instrument: &Instrument) -> Result<u8, Rejection> {
+    instrument.waveform().ok_or_else(|| Rejection {
+        reasons: vec![format!("Unsupported instrument: {:?}", instrument)],
     })
 }
 
-// Section type aliases are necessary because PackedStruct can't handle qualified field types.
-/// The music and sfx sections are contiguous in a PICO-8 ROM.
-#[derive(PackedStruct, Debug)]
-pub struct MusicSfx {
-    #[packed_field(size_bytes = "256")]
-    music: MusicSection,
-    #[packed_field(size_bytes = "4352")]
-    sfx: SfxSection,
+/// Most common value, preferring the smallest on ties.
+fn most_common<T: Ord>(values: impl Iterator<Item = T>) -> Option<T> {
+    let mut counts = BTreeMap::new();
+    for value in values {
+        *counts.entry(value).or_insert(0) += 1;
+    }
+    counts
+        .into_iter()
+        .rev()
+        .max_by_key(|(_, count)| *count)
+        .map(|(value, _)| value)
 }
 
-#[derive(Debug)]
-struct Wasm4Sfx {
-    frames_per_tone: u8,
-    loop_restart: Option<usize>,
-    tones: Vec<Wasm4Tone>,
+/// Pick the tempo and speed that get closest to a PICO-8 row duration,
+/// for trackers where a row lasts `2.5 * speed / tempo` seconds.
+/// A PICO-8 note lasts `speed * 183 / 22050` seconds.
+fn tempo_and_speed(pico8_speed: u8) -> (u8, u8) {
+    let target = pico8_speed as f64 * 183.0 / 22050.0;
+    let mut best = (150, 6);
+    let mut best_error = f64::MAX;
+    for speed in 1..=31u8 {
+        let tempo = (2.5 * speed as f64 / target).round().clamp(32.0, 255.0) as u8;
+        let error = (2.5 * speed as f64 / tempo as f64 - target).abs();
+        if error < best_error {
+            best = (tempo, speed);
+            best_error = error;
+        }
+    }
+    best
 }
 
-/// Parameters for a WASM-4 `tone(…)` call.
-/// See https://wasm4.org/docs/reference/functions/#tone-frequency-duration-volume-flags
-#[derive(Debug)]
-struct Wasm4Tone {
-    frequency: u32,
-    duration: u32,
-    volume: u32,
-    flags: u32,
-}
+/// 32 4-bit samples approximating each PICO-8 waveform, for consoles with wavetable channels.
+/// TIC-80 plays a waveform that is all zeroes as noise.
+const WAVES: [[u8; 32]; 8] = [
+    // triangle
+    [
+        0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 15, 14, 13, 12, 11, 10, 9, 8, 7, 6,
+        5, 4, 3, 2, 1, 0,
+    ],
+    // tilted saw
+    [
+        0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+        14, 15, 11, 6, 2,
+    ],
+    // saw
+    [
+        0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+        14, 14, 15, 15,
+    ],
+    // square
+    [
+        15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0, 0, 0, 0,
+    ],
+    // pulse
+    [
+        15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0, 0,
+    ],
+    // organ
+    [
+        0, 2, 4, 6, 8, 10, 12, 14, 15, 13, 11, 9, 7, 6, 5, 4, 4, 5, 6, 7, 9, 11, 13, 15, 14, 12,
+        10, 8, 6, 4, 2, 0,
+    ],
+    // noise
+    [0; 32],
+    // phaser
+    [
+        0, 2, 4, 6, 8, 10, 12, 14, 15, 14, 12, 10, 8, 6, 4, 2, 0, 1, 3, 5, 7, 9, 11, 13, 14, 12, 9,
+        7, 5, 3, 1, 0,
+    ],
+];
+
+/// Small carts for testing the targets.
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{translate, Target};
+    use crate::cartridge::Cartridge;
+    use crate::error::Error;
+    use crate::music::{Pattern, Section as MusicSection};
+    use crate::sfx::{Instrument, Note, Pitch, Section as SfxSection, Sfx};
+    use packed_struct::prelude::Integer;
+
+    pub fn note(pitch: u8, instrument: Instrument, volume: u8) -> Note {
+        let mut note = Note::default();
+        note.set_pitch(Pitch::try_from(pitch).unwrap());
+        note.set_instrument(instrument);
+        note.set_volume(Integer::from(volume));
+        note
+    }
+
+    /// An SFX that plays `notes` and then is silent.
+    pub fn sfx(speed: u8, notes: &[Note]) -> Sfx {
+        let mut sfx = Sfx {
+            speed,
+            ..Sfx::default()
+        };
+        sfx.notes[..notes.len()].clone_from_slice(notes);
+        sfx
+    }
+
+    /// A pattern that plays `sfx_ids` on the first channels, with the rest turned off.
+    pub fn pattern(sfx_ids: &[u8]) -> Pattern {
+        let mut pattern = Pattern::default();
+        for (c, channel) in pattern.channels.iter_mut().enumerate() {
+            match sfx_ids.get(c) {
+                Some(sfx_id) => channel.sfx_id = Integer::from(*sfx_id),
+                None => channel.set_enabled(false),
+            }
+        }
+        pattern
+    }
+
+    /// A cart with these SFX and patterns, and empty ones after them.
+    pub fn cart(sfxes: &[Sfx], patterns: &[Pattern]) -> Cartridge {
+        let mut cartridge = Cartridge::from_bytes(vec![0; Cartridge::LEN]).unwrap();
+        let mut sfx = SfxSection::default();
+        sfx.sfxes[..sfxes.len()].clone_from_slice(sfxes);
+        cartridge.set_sfx(&sfx).unwrap();
+        let mut music = MusicSection::default();
+        for (p, pattern) in music.patterns.iter_mut().enumerate() {
+            *pattern = patterns
+                .get(p)
+                .cloned()
+                .unwrap_or_else(|| self::pattern(&[]));
+        }
+        cartridge.set_music(&music).unwrap();
+        cartridge
+    }
+
+    #[test]
+    fn targets_parse_from_their_names() {
+        assert_eq!("wasm4".parse::<Target>().unwrap(), Target::Wasm4);
+        assert_eq!("TIC80".parse::<Target>().unwrap(), Target::Tic80);
+        assert_eq!("hugedriver".parse::<Target>().unwrap(), Target::Hugedriver);
+        assert_eq!(
+            "FamiTracker".parse::<Target>().unwrap(),
+            Target::Famitracker
+        );
+        assert!(matches!(
+            "nes".parse::<Target>(),
+            Err(Error::Target(name)) if name == "nes"
+        ));
+    }
 
-#[derive(Debug)]
-struct Wasm4Pattern {
-    loop_start: bool,
-    loop_back: bool,
-    stop_at_end: bool,
-    sfx_ids: Vec<usize>,
+    #[test]
+    fn every_target_rejects_out_of_range_loops() {
+        let mut sfxes = vec![];
+        for (loop_start, loop_end) in [(40, 0), (0, 40), (40, 50), (4, 40)] {
+            let mut looped = sfx(16, &[note(24, Instrument::Square, 5)]);
+            looped.loop_start = loop_start;
+            looped.loop_end = loop_end;
+            sfxes.push(looped);
+        }
+        let cartridge = cart(&sfxes, &[pattern(&[0, 1, 2, 3])]);
+        for target in [
+            Target::Wasm4,
+            Target::Tic80,
+            Target::Hugedriver,
+            Target::Famitracker,
+        ] {
+            let mut report = crate::report::Report::default();
+            translate(&cartridge, target, &mut report).unwrap();
+            let messages = report.messages();
+            for (i, message) in messages[..4].iter().enumerate() {
+                assert!(
+                    message.starts_with(&format!("Skipping SFX {}: SFX loop", i)),
+                    "{:?}: {}",
+                    target,
+                    message
+                );
+            }
+        }
+    }
 }
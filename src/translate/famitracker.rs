@@ -0,0 +1,724 @@
+use super::{
+    check_sfx, ignore_filters, most_common, played_length, tempo_and_speed, waveform, MusicSfx,
+};
+use crate::music::Pattern;
+use crate::report::{Mapped, Reasons, Rejection, Report};
+use crate::sfx::{Effect, Instrument, Sfx};
+use packed_struct::PrimitiveEnum;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter, Write};
+
+/// FamiTracker has 64 instruments, numbered 00–3F.
+const INSTRUMENT_COUNT: usize = 64;
+/// Pattern number used for channels with nothing to play. FamiTracker treats missing patterns as empty.
+const EMPTY_PATTERN: usize = 0x40;
+
+/// PICO-8 SFX become FamiTracker patterns with the same number,
+/// and each enabled PICO-8 music pattern becomes a frame.
+/// Each SFX is assigned to the 2A03 channel that suits most of its notes.
+/// Instruments are generated for each PICO-8 waveform and effect,
+/// with volume, arpeggio, and pitch macros standing in for PICO-8 effects.
+/// Output is a FamiTracker text export, which can be imported by FamiTracker and Dn-FamiTracker.
+/// See http://famitracker.com/wiki/index.php?title=Text_export
+pub fn translate(
+    section: &MusicSfx,
+    report: &mut Report,
+    out: &mut dyn Write,
+) -> crate::Result<()> {
+    // FamiTracker speed and tempo are per song, so use the most common speed among the SFX used by music.
+    let pico8_speed = most_common(section.music.patterns.iter().flat_map(|pattern| {
+        pattern
+            .channels
+            .iter()
+            .filter(|c| c.enabled())
+            .map(|c| &section.sfx.sfxes[u8::from(c.sfx_id) as usize])
+            .filter(|sfx| check_sfx(sfx).is_ok())
+            .map(|sfx| sfx.speed)
+    }))
+    .unwrap_or(16);
+    let (tempo, speed) = tempo_and_speed(pico8_speed);
+
+    let mut instruments = Instruments::default();
+    let mut ftsfxes = BTreeMap::new();
+    for (i, sfx) in section.sfx.sfxes.iter().enumerate() {
+        let result = map_sfx(sfx, pico8_speed, speed, &mut instruments);
+        report.record_sfx(i, &result);
+        if let Ok(mapped) = result {
+            ftsfxes.insert(i, mapped.value);
+        }
+    }
+
+    let mut frames = Vec::with_capacity(section.music.patterns.len());
+    for (i, pattern) in section.music.patterns.iter().enumerate() {
+        let result = map_music(pattern, &ftsfxes);
+        report.record_pattern(i, &result);
+        if let Ok(mapped) = result {
+            frames.push(mapped.value);
+        }
+    }
+
+    writeln!(out, "# FamiTracker text export 0.4.2")?;
+    writeln!(out)?;
+    writeln!(out, "# Song information")?;
+    writeln!(out, "TITLE           \"\"")?;
+    writeln!(out, "AUTHOR          \"\"")?;
+    writeln!(out, "COPYRIGHT       \"\"")?;
+    writeln!(out)?;
+    writeln!(out, "# Song comment")?;
+    writeln!(out, "COMMENT \"Translated from PICO-8\"")?;
+    writeln!(out)?;
+    writeln!(out, "# Global settings")?;
+    writeln!(out, "MACHINE         0")?;
+    writeln!(out, "FRAMERATE       0")?;
+    writeln!(out, "EXPANSION       0")?;
+    writeln!(out, "VIBRATO         1")?;
+    writeln!(out, "SPLIT           32")?;
+    writeln!(out)?;
+
+    writeln!(out, "# Macros")?;
+    for (kind, macros) in instruments.macros.iter() {
+        for (i, m) in macros.iter().enumerate() {
+            let values = m.values.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+            writeln!(
+                out,
+                "MACRO {:3} {:3} {:3}  -1 {:3} : {}",
+                *kind as u8,
+                i,
+                m.loop_point,
+                m.setting,
+                values.join(" ")
+            )?;
+        }
+    }
+    writeln!(out)?;
+    writeln!(out, "# DPCM samples")?;
+    writeln!(out)?;
+
+    writeln!(out, "# Instruments")?;
+    for (i, instrument) in instruments.entries.iter().enumerate() {
+        let index = |kind: MacroKind| {
+            instrument
+                .macros
+                .iter()
+                .find(|(k, _)| *k == kind)
+                .map(|(_, index)| *index as i32)
+                .unwrap_or(-1)
+        };
+        writeln!(
+            out,
+            "INST2A03 {:3} {:5} {:3} {:3} {:3} {:3} \"{}\"",
+            i,
+            index(MacroKind::Volume),
+            index(MacroKind::Arpeggio),
+            index(MacroKind::Pitch),
+            -1,
+            index(MacroKind::Duty),
+            instrument.name
+        )?;
+    }
+    writeln!(out)?;
+
+    writeln!(out, "# Tracks")?;
+    writeln!(out)?;
+    writeln!(
+        out,
+        "TRACK {:3} {:3} {:3} \"PICO-8 music\"",
+        ROWS, speed, tempo
+    )?;
+    writeln!(out, "COLUMNS : 1 1 1 1 1")?;
+    writeln!(out)?;
+    for (i, frame) in frames.iter().enumerate() {
+        let patterns = frame
+            .iter()
+            .map(|sfx_id| format!("{:02X}", sfx_id.unwrap_or(EMPTY_PATTERN)))
+            .collect::<Vec<_>>();
+        writeln!(
+            out,
+            "ORDER {:02X} : {} {:02X}",
+            i,
+            patterns.join(" "),
+            EMPTY_PATTERN
+        )?;
+    }
+    writeln!(out)?;
+
+    // Each channel has its own pattern numbers, so a pattern holds an SFX on every channel that plays it.
+    for (sfx_id, ftsfx) in ftsfxes.iter() {
+        let channels = (0..4)
+            .map(|c| frames.iter().any(|frame| frame[c] == Some(*sfx_id)))
+            .collect::<Vec<_>>();
+        if !channels.iter().any(|used| *used) {
+            continue;
+        }
+        writeln!(out, "PATTERN {:02X}", sfx_id)?;
+        for (r, cell) in ftsfx.cells.iter().enumerate() {
+            let columns = channels
+                .iter()
+                .map(|used| {
+                    if *used {
+                        cell.to_string()
+                    } else {
+                        Cell::EMPTY.to_string()
+                    }
+                })
+                .collect::<Vec<_>>();
+            writeln!(
+                out,
+                "ROW {:02X} : {} : {}",
+                r,
+                columns.join(" : "),
+                Cell::EMPTY
+            )?;
+        }
+        writeln!(out)?;
+    }
+
+    writeln!(out, "# End of export")?;
+    Ok(())
+}
+
+/// Rows in each FamiTracker pattern, one per PICO-8 note.
+const ROWS: usize = 32;
+
+/// 2A03 channel types.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Kind {
+    Pulse,
+    Triangle,
+    Noise,
+}
+
+impl Kind {
+    fn of(instrument: &Instrument) -> Kind {
+        match instrument {
+            Instrument::Square | Instrument::Pulse | Instrument::Saw | Instrument::TiltedSaw => {
+                Kind::Pulse
+            }
+            Instrument::Noise => Kind::Noise,
+            _ => Kind::Triangle,
+        }
+    }
+
+    /// Whether the channel can play the instrument without approximating it.
+    fn native(&self, instrument: &Instrument) -> bool {
+        matches!(
+            (self, instrument),
+            (Kind::Pulse, Instrument::Square)
+                | (Kind::Pulse, Instrument::Pulse)
+                | (Kind::Triangle, Instrument::Triangle)
+                | (Kind::Noise, Instrument::Noise)
+        )
+    }
+}
+
+/// Assigns a PICO-8 pattern's channels to 2A03 channels: pulse 1, pulse 2, triangle, and noise.
+fn map_music(
+    pattern: &Pattern,
+    ftsfxes: &BTreeMap<usize, FtSfx>,
+) -> Result<Mapped<[Option<usize>; 4]>, Rejection> {
+    if !pattern.enabled() {
+        return Err(Rejection::from("No channels in this pattern"));
+    }
+
+    let mut approximations = Reasons::default();
+    if pattern.loop_start() || pattern.loop_back() || pattern.stop_at_end() {
+        approximations.push(
+            "FamiTracker songs always loop back to their first frame, so loop and stop flags are ignored"
+                .to_string(),
+        );
+    }
+
+    let mut frame = [None; 4];
+    for (i, channel) in pattern.channels.iter().enumerate() {
+        if !channel.enabled() {
+            continue;
+        }
+        let sfx_id = u8::from(channel.sfx_id) as usize;
+        let ftsfx = match ftsfxes.get(&sfx_id) {
+            Some(ftsfx) => ftsfx,
+            None => {
+                approximations.push(format!(
+                    "Channel {} plays SFX {}, which was not translated",
+                    i, sfx_id
+                ));
+                continue;
+            }
+        };
+        if let Some(speed) = ftsfx.mismatched_speed {
+            approximations.push(format!(
+                "Channel {} plays SFX {} at speed {}, which differs from the song speed",
+                i, sfx_id, speed
+            ));
+        }
+        let candidates: &[usize] = match ftsfx.kind {
+            Kind::Pulse => &[0, 1],
+            Kind::Triangle => &[2],
+            Kind::Noise => &[3],
+        };
+        match candidates.iter().find(|c| frame[**c].is_none()) {
+            Some(&c) => frame[c] = Some(sfx_id),
+            None => approximations.push(format!(
+                "Channel {} dropped: no free 2A03 {:?} channel for SFX {}",
+                i, ftsfx.kind, sfx_id
+            )),
+        }
+    }
+
+    Ok(Mapped {
+        value: frame,
+        approximations: approximations.into_vec(),
+    })
+}
+
+fn map_sfx(
+    sfx: &Sfx,
+    song_speed: u8,
+    ticks_per_row: u8,
+    instruments: &mut Instruments,
+) -> Result<Mapped<FtSfx>, Rejection> {
+    check_sfx(sfx)?;
+
+    let mut approximations = Reasons::default();
+    ignore_filters(sfx, &mut approximations);
+
+    let size = played_length(sfx, &mut approximations);
+
+    // Pick the channel type that suits most audible notes.
+    let audible = || {
+        sfx.notes[..size]
+            .iter()
+            .filter(|n| u8::from(n.volume()) > 0)
+    };
+    let kind = most_common(audible().map(|n| Kind::of(&n.instrument()))).unwrap_or(Kind::Pulse);
+    if kind == Kind::Triangle {
+        let mut volumes = audible().map(|n| u8::from(n.volume())).collect::<Vec<_>>();
+        volumes.sort_unstable();
+        volumes.dedup();
+        if volumes.len() > 1 {
+            approximations.push("Volume changes ignored on the triangle channel".to_string());
+        }
+    }
+
+    let mut cells = Vec::with_capacity(ROWS);
+    let mut previous = None;
+    for (i, note) in sfx.notes.iter().enumerate() {
+        let volume = u8::from(note.volume());
+        if i >= size || volume == 0 {
+            cells.push(if previous.is_some() {
+                Cell::CUT
+            } else {
+                Cell::EMPTY
+            });
+            previous = None;
+            continue;
+        }
+
+        let instrument = note.instrument();
+        if !kind.native(&instrument) {
+            approximations.push(format!(
+                "{:?} notes played on the 2A03 {:?} channel",
+                instrument, kind
+            ));
+        }
+
+        let mut pitch = u8::from(note.pitch());
+        let mut command = None;
+        let mut arpeggio = None;
+        match note.effect() {
+            Effect::Slide => match previous {
+                // Start from the previous note and slide to this one.
+                Some(from) if from != pitch => {
+                    let distance = (pitch as i16 - from as i16).unsigned_abs().min(15) as u8;
+                    if distance == 15 {
+                        approximations.push("Slide shortened to 15 semitones".to_string());
+                    }
+                    let direction = if pitch > from { 'Q' } else { 'R' };
+                    command = Some(format!("{}4{:X}", direction, distance));
+                    pitch = from;
+                }
+                _ => (),
+            },
+            Effect::ArpeggioFast | Effect::ArpeggioSlow => {
+                // Arpeggios play all 4 notes of an aligned group, starting from the group's first note.
+                let group = &sfx.notes[i & !3..(i & !3) + 4];
+                let base = u8::from(group[0].pitch());
+                let mut intervals = [0i8; 4];
+                for (interval, n) in intervals.iter_mut().zip(group.iter()) {
+                    *interval = (u8::from(n.pitch()) as i16 - base as i16) as i8;
+                }
+                arpeggio = Some(intervals);
+                pitch = base;
+            }
+            _ => (),
+        }
+        previous = Some(u8::from(note.pitch()));
+
+        let instrument = instruments.get(
+            InstrumentKey {
+                waveform: waveform(&instrument)?,
+                // Slides use an effect column instead of macros.
+                effect: match note.effect() {
+                    Effect::Slide => Effect::None,
+                    effect => effect,
+                }
+                .to_primitive(),
+                arpeggio,
+            },
+            ticks_per_row,
+            &mut approximations,
+        );
+
+        cells.push(Cell {
+            note: Note::Play(match kind {
+                // The noise channel has 16 pitches, written as 0-# through F-#.
+                Kind::Noise => format!("{:X}-#", pitch / 4),
+                _ => format!(
+                    "{}{}",
+                    NOTE_NAMES[(pitch % 12) as usize],
+                    // PICO-8 octave 0 is MTS octave 2.
+                    pitch / 12 + 2
+                ),
+            }),
+            instrument: Some(instrument),
+            volume: match kind {
+                Kind::Triangle => None,
+                _ => Some(((volume as u16 * 15 + 3) / 7) as u8),
+            },
+            command,
+        });
+    }
+
+    Ok(Mapped {
+        value: FtSfx {
+            kind,
+            mismatched_speed: if sfx.speed == song_speed {
+                None
+            } else {
+                Some(sfx.speed)
+            },
+            cells,
+        },
+        approximations: approximations.into_vec(),
+    })
+}
+
+const NOTE_NAMES: [&str; 12] = [
+    "C-", "C#", "D-", "D#", "E-", "F-", "F#", "G-", "G#", "A-", "A#", "B-",
+];
+
+struct FtSfx {
+    kind: Kind,
+    /// PICO-8 speed, if it's not the one the song plays at.
+    mismatched_speed: Option<u8>,
+    cells: Vec<Cell>,
+}
+
+#[derive(Clone, Debug)]
+enum Note {
+    None,
+    /// `---`
+    Cut,
+    Play(String),
+}
+
+/// One channel of one row of a FamiTracker pattern.
+#[derive(Clone, Debug)]
+struct Cell {
+    note: Note,
+    instrument: Option<usize>,
+    volume: Option<u8>,
+    command: Option<String>,
+}
+
+impl Cell {
+    const EMPTY: Cell = Cell {
+        note: Note::None,
+        instrument: None,
+        volume: None,
+        command: None,
+    };
+
+    const CUT: Cell = Cell {
+        note: Note::Cut,
+        instrument: None,
+        volume: None,
+        command: None,
+    };
+}
+
+impl Display for Cell {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.note {
+            Note::None => write!(f, "...")?,
+            Note::Cut => write!(f, "---")?,
+            Note::Play(note) => write!(f, "{}", note)?,
+        }
+        match self.instrument {
+            None => write!(f, " ..")?,
+            Some(instrument) => write!(f, " {:02X}", instrument)?,
+        }
+        match self.volume {
+            None => write!(f, " .")?,
+            Some(volume) => write!(f, " {:X}", volume)?,
+        }
+        match &self.command {
+            None => write!(f, " ..."),
+            Some(command) => write!(f, " {}", command),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum MacroKind {
+    Volume = 0,
+    Arpeggio = 1,
+    Pitch = 2,
+    Duty = 4,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Macro {
+    /// Index of the value to loop back to, or -1 to hold the last value.
+    loop_point: i32,
+    /// For arpeggios, 0 is absolute. For pitch, 0 is relative.
+    setting: u8,
+    values: Vec<i8>,
+}
+
+/// What distinguishes one generated instrument from another.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct InstrumentKey {
+    waveform: u8,
+    effect: u8,
+    arpeggio: Option<[i8; 4]>,
+}
+
+struct FtInstrument {
+    key: InstrumentKey,
+    name: String,
+    macros: Vec<(MacroKind, usize)>,
+}
+
+/// Generated instruments, and the macros they use.
+#[derive(Default)]
+struct Instruments {
+    entries: Vec<FtInstrument>,
+    macros: BTreeMap<MacroKind, Vec<Macro>>,
+}
+
+impl Instruments {
+    /// Find or create the instrument for a waveform and effect.
+    /// If we run out of instruments, fall back to the waveform's plain instrument.
+    fn get(
+        &mut self,
+        key: InstrumentKey,
+        ticks_per_row: u8,
+        approximations: &mut Reasons,
+    ) -> usize {
+        if let Some(i) = self.entries.iter().position(|e| e.key == key) {
+            return i;
+        }
+        if self.entries.len() >= INSTRUMENT_COUNT - 8 && key.effect != 0 {
+            approximations.push("Out of FamiTracker instruments; effect ignored".to_string());
+            return self.get(
+                InstrumentKey {
+                    waveform: key.waveform,
+                    effect: 0,
+                    arpeggio: None,
+                },
+                ticks_per_row,
+                approximations,
+            );
+        }
+
+        let mut macros = vec![];
+        // 2A03 pulse duty: 0 is 12.5%, 1 is 25%, 2 is 50%.
+        let duty = match key.waveform {
+            1 | 2 => Some(0),
+            3 => Some(2),
+            4 => Some(1),
+            _ => None,
+        };
+        if let Some(duty) = duty {
+            macros.push(self.add_macro(
+                MacroKind::Duty,
+                Macro {
+                    loop_point: -1,
+                    setting: 0,
+                    values: vec![duty],
+                },
+            ));
+        }
+
+        let ticks = ticks_per_row.max(1) as i32;
+        let effect = Effect::from_primitive(key.effect).unwrap_or_default();
+        let envelope = match effect {
+            Effect::FadeIn => Some((
+                MacroKind::Volume,
+                Macro {
+                    loop_point: -1,
+                    setting: 0,
+                    values: (1..=ticks).map(|t| (t * 15 / ticks) as i8).collect(),
+                },
+            )),
+            Effect::FadeOut => Some((
+                MacroKind::Volume,
+                Macro {
+                    loop_point: -1,
+                    setting: 0,
+                    values: (0..=ticks).map(|t| (15 - t * 15 / ticks) as i8).collect(),
+                },
+            )),
+            Effect::Vibrato => Some((
+                MacroKind::Pitch,
+                Macro {
+                    loop_point: 0,
+                    setting: 0,
+                    values: vec![1, 1, -1, -1, -1, -1, 1, 1],
+                },
+            )),
+            // Positive pitch values lower the pitch.
+            Effect::Drop => Some((
+                MacroKind::Pitch,
+                Macro {
+                    loop_point: 0,
+                    setting: 0,
+                    values: vec![4],
+                },
+            )),
+            Effect::ArpeggioFast | Effect::ArpeggioSlow => {
+                let hold = if effect == Effect::ArpeggioFast { 2 } else { 4 };
+                Some((
+                    MacroKind::Arpeggio,
+                    Macro {
+                        loop_point: 0,
+                        setting: 0,
+                        values: key
+                            .arpeggio
+                            .unwrap_or_default()
+                            .iter()
+                            .flat_map(|interval| std::iter::repeat_n(*interval, hold))
+                            .collect(),
+                    },
+                ))
+            }
+            Effect::None | Effect::Slide => None,
+        };
+        if let Some((kind, m)) = envelope {
+            macros.push(self.add_macro(kind, m));
+        }
+
+        let name = match effect {
+            Effect::None => WAVEFORM_NAMES[key.waveform as usize].to_string(),
+            effect => format!("{} {:?}", WAVEFORM_NAMES[key.waveform as usize], effect),
+        };
+        self.entries.push(FtInstrument { key, name, macros });
+        self.entries.len() - 1
+    }
+
+    fn add_macro(&mut self, kind: MacroKind, m: Macro) -> (MacroKind, usize) {
+        let macros = self.macros.entry(kind).or_default();
+        let index = match macros.iter().position(|existing| *existing == m) {
+            Some(index) => index,
+            None => {
+                macros.push(m);
+                macros.len() - 1
+            }
+        };
+        (kind, index)
+    }
+}
+
+const WAVEFORM_NAMES: [&str; 8] = [
+    "Triangle",
+    "Tilted saw",
+    "Saw",
+    "Square",
+    "Pulse",
+    "Organ",
+    "Noise",
+    "Phaser",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::Outcome;
+    use crate::translate::tests::{cart, note, pattern, sfx};
+    use crate::translate::{translate, Target};
+    use packed_struct::prelude::Integer;
+
+    #[test]
+    fn small_song_exports_as_text() {
+        let cartridge = cart(
+            &[
+                sfx(
+                    16,
+                    &[
+                        note(24, Instrument::Square, 5),
+                        note(26, Instrument::Square, 7),
+                    ],
+                ),
+                sfx(16, &[note(24, Instrument::Sfx(Integer::from(2)), 5)]),
+            ],
+            &[pattern(&[0, 1])],
+        );
+        let mut report = Report::default();
+        let out = translate(&cartridge, Target::Famitracker, &mut report).unwrap();
+
+        let empty = "... .. . ... : ... .. . ... : ... .. . ... : ... .. . ... : ... .. . ...";
+        let mut expected = r#"# FamiTracker text export 0.4.2
+
+# Song information
+TITLE           ""
+AUTHOR          ""
+COPYRIGHT       ""
+
+# Song comment
+COMMENT "Translated from PICO-8"
+
+# Global settings
+MACHINE         0
+FRAMERATE       0
+EXPANSION       0
+VIBRATO         1
+SPLIT           32
+
+# Macros
+MACRO   4   0  -1  -1   0 : 2
+
+# DPCM samples
+
+# Instruments
+INST2A03   0    -1  -1  -1  -1   0 "Square"
+
+# Tracks
+
+TRACK  32   6 113 "PICO-8 music"
+COLUMNS : 1 1 1 1 1
+
+ORDER 00 : 00 40 40 40 40
+
+PATTERN 00
+ROW 00 : C-4 00 B ... : ... .. . ... : ... .. . ... : ... .. . ... : ... .. . ...
+ROW 01 : D-4 00 F ... : ... .. . ... : ... .. . ... : ... .. . ... : ... .. . ...
+ROW 02 : --- .. . ... : ... .. . ... : ... .. . ... : ... .. . ... : ... .. . ...
+"#
+        .to_string();
+        for r in 3..ROWS {
+            expected.push_str(&format!("ROW {:02X} : {}\n", r, empty));
+        }
+        expected.push_str("\n# End of export\n");
+        assert_eq!(expected, out);
+
+        assert!(matches!(
+            &report.sfx[1].outcome,
+            Outcome::Rejected { reasons } if reasons == &["Unsupported instrument: Sfx(2)"]
+        ));
+        assert!(matches!(
+            &report.patterns[0].outcome,
+            Outcome::Approximated { approximations }
+                if approximations == &["Channel 1 plays SFX 1, which was not translated"]
+        ));
+    }
+}
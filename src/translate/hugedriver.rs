@@ -0,0 +1,510 @@
+use super::{check_sfx, ignore_filters, most_common, played_length, waveform, MusicSfx, WAVES};
+use crate::music::Pattern;
+use crate::report::{Mapped, Reasons, Rejection, Report};
+use crate::sfx::{Effect, Instrument, Sfx};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter, Write};
+
+/// Rows used by each hUGEDriver pattern, one per PICO-8 note.
+/// hUGEDriver patterns always have 64 rows, so the last one we use breaks to the next order.
+const ROWS: usize = 32;
+const PATTERN_ROWS: usize = 64;
+
+/// PICO-8 SFX become hUGEDriver patterns, and each enabled PICO-8 music pattern becomes an order.
+/// Each SFX is assigned to the Game Boy channel that suits most of its notes:
+/// square and pulse to the duty channels, noise to the noise channel,
+/// and everything else to the wave channel with a generated wavetable.
+/// Output is GBDK C source for a hUGEDriver 6 song.
+/// See https://github.com/SuperDisk/hUGEDriver
+pub fn translate(
+    section: &MusicSfx,
+    report: &mut Report,
+    out: &mut dyn Write,
+) -> crate::Result<()> {
+    let mut gbsfxes = BTreeMap::new();
+    for (i, sfx) in section.sfx.sfxes.iter().enumerate() {
+        let result = map_sfx(sfx);
+        report.record_sfx(i, &result);
+        if let Ok(mapped) = result {
+            gbsfxes.insert(i, mapped.value);
+        }
+    }
+
+    let speed = song_speed(&section.music.patterns, &gbsfxes);
+    let mut orders = Vec::with_capacity(section.music.patterns.len());
+    for (i, pattern) in section.music.patterns.iter().enumerate() {
+        let result = map_music(pattern, &gbsfxes, speed);
+        report.record_pattern(i, &result);
+        if let Ok(mapped) = result {
+            orders.push((i, mapped.value));
+        }
+    }
+
+    writeln!(out, "#include \"hUGEDriver.h\"")?;
+    writeln!(out, "#include <stddef.h>")?;
+    writeln!(out)?;
+
+    // hUGEDriver counts orders in bytes of pointer table.
+    writeln!(
+        out,
+        "static const unsigned char order_cnt = {};",
+        orders.len() * 2
+    )?;
+    writeln!(out)?;
+
+    let mut used = orders
+        .iter()
+        .flat_map(|(_, order)| order.iter().flatten().copied())
+        .collect::<Vec<_>>();
+    used.sort_unstable();
+    used.dedup();
+    for sfx_id in used {
+        writeln!(out, "static const unsigned char P{}[] = {{", sfx_id)?;
+        for row in gbsfxes[&sfx_id].rows.iter() {
+            writeln!(out, "    {},", row)?;
+        }
+        writeln!(out, "}};")?;
+    }
+    writeln!(out, "static const unsigned char P_EMPTY[] = {{")?;
+    for row in Row::empty_pattern().iter() {
+        writeln!(out, "    {},", row)?;
+    }
+    writeln!(out, "}};")?;
+    writeln!(out)?;
+
+    for channel in 0..4 {
+        writeln!(
+            out,
+            "static const unsigned char* const order{}[] = {{",
+            channel + 1
+        )?;
+        for (i, order) in orders.iter() {
+            match order[channel] {
+                Some(sfx_id) => writeln!(out, "    P{}, // PICO-8 pattern {}", sfx_id, i)?,
+                None => writeln!(out, "    P_EMPTY, // PICO-8 pattern {}", i)?,
+            }
+        }
+        writeln!(out, "}};")?;
+    }
+    writeln!(out)?;
+
+    writeln!(out, "static const hUGEDutyInstr_t duty_instruments[] = {{")?;
+    for duty in DUTIES {
+        for volume in 1..=7 {
+            writeln!(out, "    {{8,{},{},NULL,128}},", duty, envelope(volume))?;
+        }
+    }
+    writeln!(out, "}};")?;
+
+    writeln!(out, "static const hUGEWaveInstr_t wave_instruments[] = {{")?;
+    for waveform in WAVE_CHANNEL_WAVEFORMS {
+        for level in WAVE_LEVELS {
+            writeln!(out, "    {{0,{},{},NULL,128}},", level, waveform)?;
+        }
+    }
+    writeln!(out, "}};")?;
+
+    writeln!(
+        out,
+        "static const hUGENoiseInstr_t noise_instruments[] = {{"
+    )?;
+    for volume in 1..=7 {
+        writeln!(out, "    {{{},NULL,128}},", envelope(volume))?;
+    }
+    writeln!(out, "}};")?;
+    writeln!(out)?;
+
+    writeln!(out, "static const unsigned char waves[] = {{")?;
+    for wave in WAVES.iter() {
+        let bytes = wave
+            .chunks(2)
+            .map(|pair| (pair[0] << 4 | pair[1]).to_string())
+            .collect::<Vec<_>>();
+        writeln!(out, "    {},", bytes.join(","))?;
+    }
+    writeln!(out, "}};")?;
+    writeln!(out)?;
+
+    writeln!(
+        out,
+        "const hUGESong_t song = {{{}, &order_cnt, order1, order2, order3, order4, duty_instruments, wave_instruments, noise_instruments, NULL, waves}};",
+        ticks_per_row(speed)
+    )?;
+    Ok(())
+}
+
+/// Game Boy channel types.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Kind {
+    Duty,
+    Wave,
+    Noise,
+}
+
+impl Kind {
+    fn of(instrument: &Instrument) -> Kind {
+        match instrument {
+            Instrument::Square | Instrument::Pulse => Kind::Duty,
+            Instrument::Noise => Kind::Noise,
+            _ => Kind::Wave,
+        }
+    }
+}
+
+/// `len_duty` register values for PICO-8 square (50%) and pulse (25%).
+const DUTIES: [u8; 2] = [0x80, 0x40];
+
+/// PICO-8 waveforms played on the wave channel, which are also their indexes in `waves`.
+const WAVE_CHANNEL_WAVEFORMS: [u8; 5] = [0, 1, 2, 5, 7];
+
+/// Wave channel output levels: 100%, 50%, and 25%.
+const WAVE_LEVELS: [u8; 3] = [0x20, 0x40, 0x60];
+
+/// Volume envelope register with a constant volume.
+fn envelope(volume: u8) -> u8 {
+    ((volume as u16 * 15 + 3) / 7) as u8 * 16
+}
+
+/// hUGEDriver counts ticks at 60 Hz; a PICO-8 note lasts `speed * 183 / 22050` seconds.
+fn ticks_per_row(speed: u8) -> u8 {
+    ((speed as f64 * 183.0 * 60.0 / 22050.0).round() as u8).max(1)
+}
+
+/// hUGEDriver speed is per song, so use the most common speed among the SFX used by music.
+fn song_speed(patterns: &[Pattern], gbsfxes: &BTreeMap<usize, GbSfx>) -> u8 {
+    most_common(patterns.iter().flat_map(|pattern| {
+        pattern
+            .channels
+            .iter()
+            .filter(|c| c.enabled())
+            .filter_map(|c| gbsfxes.get(&(u8::from(c.sfx_id) as usize)))
+            .map(|gbsfx| gbsfx.speed)
+    }))
+    .unwrap_or(16)
+}
+
+/// Assigns a PICO-8 pattern's channels to Game Boy channels: duty 1, duty 2, wave, and noise.
+fn map_music(
+    pattern: &Pattern,
+    gbsfxes: &BTreeMap<usize, GbSfx>,
+    song_speed: u8,
+) -> Result<Mapped<[Option<usize>; 4]>, Rejection> {
+    if !pattern.enabled() {
+        return Err(Rejection::from("No channels in this pattern"));
+    }
+
+    let mut approximations = Reasons::default();
+    if pattern.loop_start() || pattern.loop_back() || pattern.stop_at_end() {
+        approximations.push(
+            "hUGEDriver songs always loop back to their first order, so loop and stop flags are ignored"
+                .to_string(),
+        );
+    }
+
+    let mut order = [None; 4];
+    for (i, channel) in pattern.channels.iter().enumerate() {
+        if !channel.enabled() {
+            continue;
+        }
+        let sfx_id = u8::from(channel.sfx_id) as usize;
+        let gbsfx = match gbsfxes.get(&sfx_id) {
+            Some(gbsfx) => gbsfx,
+            None => {
+                approximations.push(format!(
+                    "Channel {} plays SFX {}, which was not translated",
+                    i, sfx_id
+                ));
+                continue;
+            }
+        };
+        if gbsfx.speed != song_speed {
+            approximations.push(format!(
+                "Channel {} plays SFX {} at speed {}, but the song plays at speed {}",
+                i, sfx_id, gbsfx.speed, song_speed
+            ));
+        }
+        let candidates: &[usize] = match gbsfx.kind {
+            Kind::Duty => &[0, 1],
+            Kind::Wave => &[2],
+            Kind::Noise => &[3],
+        };
+        match candidates.iter().find(|c| order[**c].is_none()) {
+            Some(&c) => order[c] = Some(sfx_id),
+            None => approximations.push(format!(
+                "Channel {} dropped: no free Game Boy {:?} channel for SFX {}",
+                i, gbsfx.kind, sfx_id
+            )),
+        }
+    }
+
+    Ok(Mapped {
+        value: order,
+        approximations: approximations.into_vec(),
+    })
+}
+
+fn map_sfx(sfx: &Sfx) -> Result<Mapped<GbSfx>, Rejection> {
+    check_sfx(sfx)?;
+
+    let mut approximations = Reasons::default();
+    ignore_filters(sfx, &mut approximations);
+
+    let size = played_length(sfx, &mut approximations);
+
+    // Pick the channel type that suits most audible notes.
+    let kind = most_common(
+        sfx.notes[..size]
+            .iter()
+            .filter(|n| u8::from(n.volume()) > 0)
+            .map(|n| Kind::of(&n.instrument())),
+    )
+    .unwrap_or(Kind::Wave);
+
+    let mut rows = Vec::with_capacity(PATTERN_ROWS);
+    let mut sounding = false;
+    for (i, note) in sfx.notes.iter().enumerate() {
+        let volume = u8::from(note.volume());
+        if i >= size || volume == 0 {
+            rows.push(if sounding { Row::CUT } else { Row::EMPTY });
+            sounding = false;
+            continue;
+        }
+        sounding = true;
+
+        let instrument = note.instrument();
+        if Kind::of(&instrument) != kind {
+            approximations.push(format!(
+                "{:?} notes played on the Game Boy {:?} channel",
+                instrument, kind
+            ));
+        }
+        let instrument = match kind {
+            Kind::Duty => {
+                let duty = match instrument {
+                    Instrument::Pulse => 1,
+                    _ => 0,
+                };
+                duty * 7 + volume
+            }
+            Kind::Wave => {
+                if volume != 7 {
+                    approximations
+                        .push("Volume rounded to the wave channel's output levels".to_string());
+                }
+                let number = waveform(&instrument)?;
+                let waveform = WAVE_CHANNEL_WAVEFORMS
+                    .iter()
+                    .position(|w| *w == number)
+                    .unwrap_or(0) as u8;
+                let level = match volume {
+                    6..=7 => 0,
+                    3..=5 => 1,
+                    _ => 2,
+                };
+                waveform * 3 + level + 1
+            }
+            Kind::Noise => volume,
+        };
+
+        let effect = match note.effect() {
+            Effect::None => 0x000,
+            Effect::Slide => {
+                approximations.push("Slide effect approximated with tone portamento".to_string());
+                0x310
+            }
+            Effect::Vibrato => {
+                approximations.push("Vibrato effect approximated with vibrato".to_string());
+                0x422
+            }
+            Effect::Drop => {
+                approximations.push("Drop effect approximated with portamento down".to_string());
+                0x220
+            }
+            Effect::FadeIn => {
+                approximations.push("Fade in effect approximated with volume slide".to_string());
+                0xA10
+            }
+            Effect::FadeOut => {
+                approximations.push("Fade out effect approximated with volume slide".to_string());
+                0xA01
+            }
+            effect => {
+                approximations.push(format!("Effect ignored: {:?}", effect));
+                0x000
+            }
+        };
+
+        rows.push(Row {
+            // hUGEDriver's lowest note, C_3, is PICO-8 pitch 0.
+            note: Some(u8::from(note.pitch())),
+            instrument,
+            effect,
+        });
+    }
+
+    // Break to the next order after the last PICO-8 note, which replaces its effect if it has one.
+    // A cut isn't a note effect, and the break ends the note anyway.
+    if rows[ROWS - 1].note.is_some() && rows[ROWS - 1].effect != 0 {
+        approximations.push(format!(
+            "Effect on note {} replaced by pattern break",
+            ROWS - 1
+        ));
+    }
+    rows[ROWS - 1].effect = Row::BREAK;
+    rows.resize(PATTERN_ROWS, Row::EMPTY);
+
+    Ok(Mapped {
+        value: GbSfx {
+            kind,
+            speed: sfx.speed,
+            rows,
+        },
+        approximations: approximations.into_vec(),
+    })
+}
+
+struct GbSfx {
+    kind: Kind,
+    speed: u8,
+    rows: Vec<Row>,
+}
+
+/// A single row of a hUGEDriver pattern, written with the `DN(note, instrument, effect)` macro.
+#[derive(Clone, Copy, Debug)]
+struct Row {
+    note: Option<u8>,
+    /// 1-based instrument number for the row's channel type, or 0 for none.
+    instrument: u8,
+    effect: u16,
+}
+
+impl Row {
+    const EMPTY: Row = Row {
+        note: None,
+        instrument: 0,
+        effect: 0,
+    };
+
+    /// `E00`: cut note immediately.
+    const CUT: Row = Row {
+        note: None,
+        instrument: 0,
+        effect: 0xE00,
+    };
+
+    /// `D00`: break to row 0 of the next order.
+    const BREAK: u16 = 0xD00;
+
+    fn empty_pattern() -> Vec<Row> {
+        let mut rows = vec![Row::EMPTY; PATTERN_ROWS];
+        rows[ROWS - 1].effect = Row::BREAK;
+        rows
+    }
+}
+
+impl Display for Row {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        const NAMES: [&str; 12] = [
+            "C_", "Cs", "D_", "Ds", "E_", "F_", "Fs", "G_", "Gs", "A_", "As", "B_",
+        ];
+        let note = match self.note {
+            Some(note) => format!("{}{}", NAMES[(note % 12) as usize], note / 12 + 3),
+            None => "___".to_string(),
+        };
+        write!(f, "DN({},{},0x{:03X})", note, self.instrument, self.effect)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::Outcome;
+    use crate::translate::tests::{cart, note, pattern, sfx};
+    use crate::translate::{translate, Target};
+    use packed_struct::prelude::Integer;
+
+    #[test]
+    fn notes_map_to_rows() {
+        let mapped = map_sfx(&sfx(
+            16,
+            &[
+                note(25, Instrument::Pulse, 5),
+                note(0, Instrument::Square, 7),
+            ],
+        ))
+        .unwrap();
+        assert_eq!(Kind::Duty, mapped.value.kind);
+        let rows = mapped.value.rows;
+        assert_eq!("DN(Cs5,12,0x000)", rows[0].to_string());
+        assert_eq!("DN(C_3,7,0x000)", rows[1].to_string());
+        assert_eq!("DN(___,0,0xE00)", rows[2].to_string());
+        assert_eq!("DN(___,0,0xD00)", rows[ROWS - 1].to_string());
+        assert_eq!(PATTERN_ROWS, rows.len());
+    }
+
+    #[test]
+    fn sfx_length_cuts_off_later_notes() {
+        let mut notes = vec![note(12, Instrument::Noise, 5); 4];
+        notes.extend(vec![note(12, Instrument::Square, 5); 6]);
+        let mut short = sfx(16, &notes);
+        short.loop_start = 4;
+        let mapped = map_sfx(&short).unwrap();
+        // Only the notes that are played pick the channel.
+        assert_eq!(Kind::Noise, mapped.value.kind);
+        assert_eq!("DN(C_4,5,0x000)", mapped.value.rows[3].to_string());
+        assert_eq!("DN(___,0,0xE00)", mapped.value.rows[4].to_string());
+        assert_eq!("DN(___,0,0x000)", mapped.value.rows[5].to_string());
+        assert_eq!(
+            vec!["SFX length of 4 notes padded to 32 rows"],
+            mapped.approximations
+        );
+
+        short.loop_start = 40;
+        assert!(map_sfx(&short).is_err());
+    }
+
+    #[test]
+    fn only_effects_on_the_last_note_are_replaced_by_the_break() {
+        let break_replaces = |sfx: &Sfx| {
+            map_sfx(sfx)
+                .unwrap()
+                .approximations
+                .iter()
+                .any(|a| a.ends_with("replaced by pattern break"))
+        };
+        let mut notes = vec![note(24, Instrument::Triangle, 7); ROWS - 1];
+        // Note 31 is silent, so its row cuts the note before it.
+        let cut = sfx(16, &notes);
+        assert!(!break_replaces(&cut));
+
+        let mut last = note(24, Instrument::Triangle, 7);
+        last.set_effect(Effect::Vibrato);
+        notes.push(last);
+        assert!(break_replaces(&sfx(16, &notes)));
+    }
+
+    #[test]
+    fn rejections_are_reported() {
+        let cartridge = cart(
+            &[
+                sfx(16, &[note(24, Instrument::Triangle, 7)]),
+                sfx(16, &[note(24, Instrument::Sfx(Integer::from(2)), 5)]),
+            ],
+            &[pattern(&[0, 1])],
+        );
+        let mut report = Report::default();
+        let out = translate(&cartridge, Target::Hugedriver, &mut report).unwrap();
+        assert!(out.contains("static const unsigned char P0[] = {"));
+        assert!(!out.contains("P1[]"));
+        assert!(matches!(report.sfx[0].outcome, Outcome::Translated));
+        assert!(matches!(
+            &report.sfx[1].outcome,
+            Outcome::Rejected { reasons } if reasons[0].starts_with("Unsupported instrument")
+        ));
+        assert!(matches!(
+            &report.patterns[0].outcome,
+            Outcome::Approximated { approximations }
+                if approximations == &["Channel 1 plays SFX 1, which was not translated"]
+        ));
+    }
+}
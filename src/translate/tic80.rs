@@ -0,0 +1,430 @@
+use super::{
+    check_sfx, ignore_filters, most_common, played_length, tempo_and_speed, waveform, MusicSfx,
+    WAVES,
+};
+use crate::music::Pattern;
+use crate::report::{Mapped, Reasons, Rejection, Report};
+use crate::sfx::{Effect, Sfx};
+use std::fmt::Write;
+
+/// TIC-80 has fewer music patterns than PICO-8 has SFX.
+const PATTERN_COUNT: usize = 60;
+const FRAMES_PER_TRACK: usize = 16;
+const TRACK_COUNT: usize = 8;
+/// Rows used by each TIC-80 pattern, one per PICO-8 note.
+const ROWS: usize = 32;
+const SFX_TICKS: usize = 30;
+
+/// PICO-8 SFX become TIC-80 music patterns, and PICO-8 music patterns become TIC-80 track frames,
+/// so that PICO-8 pattern `n` is frame `n % 16` of track `n / 16`.
+/// TIC-80 SFX are used as instruments: one for each PICO-8 waveform and volume.
+/// Output is the data section of a TIC-80 `.lua` cart.
+/// See https://github.com/nesbox/TIC-80/wiki/.tic-File-Format
+pub fn translate(
+    section: &MusicSfx,
+    report: &mut Report,
+    out: &mut dyn Write,
+) -> crate::Result<()> {
+    let mut tic80patterns = Vec::with_capacity(PATTERN_COUNT);
+    let mut slots = Vec::with_capacity(section.sfx.sfxes.len());
+    for (i, sfx) in section.sfx.sfxes.iter().enumerate() {
+        let result = map_sfx(sfx).and_then(|mapped| {
+            if tic80patterns.len() < PATTERN_COUNT {
+                Ok(mapped)
+            } else {
+                Err(Rejection::from("TIC-80 only has 60 music patterns"))
+            }
+        });
+        report.record_sfx(i, &result);
+        match result {
+            Ok(mapped) => {
+                tic80patterns.push(mapped.value);
+                slots.push(Slot::Pattern {
+                    id: tic80patterns.len() as u8,
+                    speed: sfx.speed,
+                });
+            }
+            Err(_) if !sfx.enabled() => slots.push(Slot::Silent),
+            Err(_) => slots.push(Slot::Missing),
+        }
+    }
+
+    let mut tic80tracks = Vec::with_capacity(TRACK_COUNT);
+    for (t, patterns) in section.music.patterns.chunks(FRAMES_PER_TRACK).enumerate() {
+        let speed = track_speed(patterns, &slots);
+        let mut frames = Vec::with_capacity(FRAMES_PER_TRACK);
+        for (f, pattern) in patterns.iter().enumerate() {
+            let result = map_music(pattern, &slots, speed);
+            report.record_pattern(t * FRAMES_PER_TRACK + f, &result);
+            frames.push(result.map(|mapped| mapped.value).unwrap_or_default());
+        }
+        tic80tracks.push(Tic80Track { frames, speed });
+    }
+
+    writeln!(out, "-- <WAVES>")?;
+    for (i, wave) in WAVES.iter().enumerate() {
+        print_line(out, i, &pack_wave(wave))?;
+    }
+    writeln!(out, "-- </WAVES>")?;
+    writeln!(out)?;
+
+    writeln!(out, "-- <SFX>")?;
+    for (i, instrument) in instruments().iter().enumerate() {
+        print_line(out, i, &instrument.pack())?;
+    }
+    writeln!(out, "-- </SFX>")?;
+    writeln!(out)?;
+
+    writeln!(out, "-- <PATTERNS>")?;
+    for (i, tic80pattern) in tic80patterns.iter().enumerate() {
+        print_line(out, i, &tic80pattern.pack())?;
+    }
+    writeln!(out, "-- </PATTERNS>")?;
+    writeln!(out)?;
+
+    writeln!(out, "-- <TRACKS>")?;
+    for (i, tic80track) in tic80tracks.iter().enumerate() {
+        print_line(out, i, &tic80track.pack())?;
+    }
+    writeln!(out, "-- </TRACKS>")?;
+    Ok(())
+}
+
+/// Print a numbered line of a `.lua` cart data section, with nibbles in each byte swapped as TIC-80 does.
+/// TIC-80 leaves out lines that are all zeroes.
+fn print_line(out: &mut dyn Write, i: usize, bytes: &[u8]) -> std::fmt::Result {
+    if bytes.iter().all(|b| *b == 0) {
+        return Ok(());
+    }
+    let hex = bytes
+        .iter()
+        .map(|b| format!("{:x}{:x}", b & 0xf, b >> 4))
+        .collect::<String>();
+    writeln!(out, "-- {:03}:{}", i, hex)
+}
+
+/// What a PICO-8 SFX turned into.
+enum Slot {
+    /// 1-based TIC-80 pattern number, and the PICO-8 speed it was written for.
+    Pattern {
+        id: u8,
+        speed: u8,
+    },
+    /// Silent SFX can be left out of a track frame without losing anything.
+    Silent,
+    Missing,
+}
+
+/// TIC-80 speed is per track, so use the most common speed among the SFX in a track's patterns.
+fn track_speed(patterns: &[Pattern], slots: &[Slot]) -> u8 {
+    most_common(patterns.iter().flat_map(|pattern| {
+        pattern
+            .channels
+            .iter()
+            .filter(|c| c.enabled())
+            .filter_map(|c| match slots[u8::from(c.sfx_id) as usize] {
+                Slot::Pattern { speed, .. } => Some(speed),
+                _ => None,
+            })
+    }))
+    .unwrap_or(16)
+}
+
+fn map_music(
+    pattern: &Pattern,
+    slots: &[Slot],
+    track_speed: u8,
+) -> Result<Mapped<[u8; 4]>, Rejection> {
+    if !pattern.enabled() {
+        return Err(Rejection::from("No channels in this pattern"));
+    }
+
+    let mut approximations = Reasons::default();
+    if pattern.loop_start() || pattern.loop_back() || pattern.stop_at_end() {
+        approximations.push(
+            "TIC-80 tracks always loop back to their first frame, so loop and stop flags are ignored"
+                .to_string(),
+        );
+    }
+
+    let mut frame = [0u8; 4];
+    for (i, channel) in pattern.channels.iter().enumerate() {
+        if !channel.enabled() {
+            continue;
+        }
+        let sfx_id = u8::from(channel.sfx_id) as usize;
+        match slots[sfx_id] {
+            Slot::Pattern { id, speed } => {
+                if speed != track_speed {
+                    approximations.push(format!(
+                        "Channel {} plays SFX {} at speed {}, but its track plays at speed {}",
+                        i, sfx_id, speed, track_speed
+                    ));
+                }
+                frame[i] = id;
+            }
+            Slot::Silent => (),
+            Slot::Missing => approximations.push(format!(
+                "Channel {} plays SFX {}, which was not translated",
+                i, sfx_id
+            )),
+        }
+    }
+
+    Ok(Mapped {
+        value: frame,
+        approximations: approximations.into_vec(),
+    })
+}
+
+fn map_sfx(sfx: &Sfx) -> Result<Mapped<Tic80Pattern>, Rejection> {
+    check_sfx(sfx)?;
+
+    let mut approximations = Reasons::default();
+    ignore_filters(sfx, &mut approximations);
+
+    let size = played_length(sfx, &mut approximations);
+
+    let mut rows = Vec::with_capacity(ROWS);
+    let mut sounding = false;
+    for (i, note) in sfx.notes.iter().enumerate() {
+        let volume = u8::from(note.volume());
+        if i >= size || volume == 0 {
+            rows.push(if sounding { Row::OFF } else { Row::default() });
+            sounding = false;
+            continue;
+        }
+        sounding = true;
+
+        let (command, param1, param2) = match note.effect() {
+            Effect::None => (Command::Empty, 0, 0),
+            Effect::Slide => {
+                approximations
+                    .push("Slide effect approximated with TIC-80 slide command".to_string());
+                // PICO-8 speed is in units of 183 samples at 22050 Hz, close to half a 60 Hz tick.
+                let ticks = (sfx.speed / 2).max(1);
+                (Command::Slide, ticks >> 4, ticks & 0xf)
+            }
+            Effect::Vibrato => {
+                approximations
+                    .push("Vibrato effect approximated with TIC-80 vibrato command".to_string());
+                (Command::Vibrato, 2, 2)
+            }
+            effect => {
+                approximations.push(format!("Effect ignored: {:?}", effect));
+                (Command::Empty, 0, 0)
+            }
+        };
+
+        let pitch = u8::from(note.pitch());
+        rows.push(Row {
+            note: NOTE_START + pitch % 12,
+            // PICO-8 octave 0 is MTS octave 2.
+            octave: pitch / 12 + 2,
+            sfx: instrument_id(waveform(&note.instrument())?, volume),
+            command,
+            param1,
+            param2,
+        });
+    }
+
+    Ok(Mapped {
+        value: Tic80Pattern { rows },
+        approximations: approximations.into_vec(),
+    })
+}
+
+/// TIC-80 SFX used as an instrument for a PICO-8 waveform and volume.
+fn instrument_id(waveform: u8, volume: u8) -> u8 {
+    waveform * 8 + volume
+}
+
+fn pack_wave(wave: &[u8; 32]) -> [u8; 16] {
+    let mut bytes = [0u8; 16];
+    for (i, pair) in wave.chunks(2).enumerate() {
+        bytes[i] = pair[0] | pair[1] << 4;
+    }
+    bytes
+}
+
+/// A TIC-80 SFX holding one waveform at one volume.
+/// See https://github.com/nesbox/TIC-80/wiki/sfx-editor
+struct Tic80Instrument {
+    wave: u8,
+    /// TIC-80 volume, 0–15.
+    volume: u8,
+}
+
+fn instruments() -> Vec<Tic80Instrument> {
+    (0u8..64)
+        .map(|id| match (id / 8, id % 8) {
+            (_, 0) => Tic80Instrument { wave: 0, volume: 0 },
+            (waveform, volume) => Tic80Instrument {
+                wave: waveform,
+                volume: ((volume as u16 * 15 + 3) / 7) as u8,
+            },
+        })
+        .collect()
+}
+
+impl Tic80Instrument {
+    fn pack(&self) -> [u8; 66] {
+        let mut bytes = [0u8; 66];
+        if self.volume == 0 {
+            return bytes;
+        }
+        for tick in 0..SFX_TICKS {
+            // TIC-80 stores volume as attenuation.
+            bytes[tick * 2] = (15 - self.volume) | self.wave << 4;
+        }
+        // Octave 4, note C, for previewing the instrument in the SFX editor.
+        bytes[SFX_TICKS * 2] = 4;
+        bytes
+    }
+}
+
+/// TIC-80 note number for C; 0 is an empty row and 1 is note off.
+const NOTE_START: u8 = 4;
+
+#[derive(Clone, Copy, Debug, Default)]
+enum Command {
+    #[default]
+    Empty = 0,
+    Slide = 4,
+    Vibrato = 6,
+}
+
+/// A single row of a TIC-80 music pattern.
+#[derive(Clone, Copy, Debug, Default)]
+struct Row {
+    note: u8,
+    octave: u8,
+    sfx: u8,
+    command: Command,
+    param1: u8,
+    param2: u8,
+}
+
+impl Row {
+    const OFF: Row = Row {
+        note: 1,
+        octave: 0,
+        sfx: 0,
+        command: Command::Empty,
+        param1: 0,
+        param2: 0,
+    };
+
+    fn pack(&self) -> [u8; 3] {
+        [
+            self.note | self.param1 << 4,
+            self.param2 | (self.command as u8) << 4 | (self.sfx >> 5) << 7,
+            (self.sfx & 0x1f) | self.octave << 5,
+        ]
+    }
+}
+
+struct Tic80Pattern {
+    rows: Vec<Row>,
+}
+
+impl Tic80Pattern {
+    /// Patterns always have room for 64 rows, even if the track only plays 32 of them.
+    fn pack(&self) -> [u8; 192] {
+        let mut bytes = [0u8; 192];
+        for (i, row) in self.rows.iter().enumerate() {
+            bytes[i * 3..i * 3 + 3].copy_from_slice(&row.pack());
+        }
+        bytes
+    }
+}
+
+struct Tic80Track {
+    /// 1-based pattern numbers for each channel, or 0 for none.
+    frames: Vec<[u8; 4]>,
+    /// PICO-8 speed of each row.
+    speed: u8,
+}
+
+impl Tic80Track {
+    fn pack(&self) -> [u8; 51] {
+        let mut bytes = [0u8; 51];
+        for (i, frame) in self.frames.iter().enumerate() {
+            // 4 channels of 6-bit pattern numbers, packed little-endian into 3 bytes.
+            let packed = frame
+                .iter()
+                .enumerate()
+                .fold(0u32, |acc, (c, id)| acc | (*id as u32) << (c * 6));
+            bytes[i * 3..i * 3 + 3].copy_from_slice(&packed.to_le_bytes()[..3]);
+        }
+        let (tempo, speed) = tempo_and_speed(self.speed);
+        // Tempo, rows, and speed are stored relative to TIC-80's defaults of 150, 64, and 6.
+        bytes[48] = (tempo as i16 - 150) as i8 as u8;
+        bytes[49] = (64 - ROWS) as u8;
+        bytes[50] = (speed as i8 - 6) as u8;
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sfx::Instrument;
+    use crate::translate::tests::{cart, note, pattern, sfx};
+    use crate::translate::{translate, Target};
+
+    #[test]
+    fn notes_map_to_rows() {
+        // C♯2 on the saw at volume 5.
+        let mapped = map_sfx(&sfx(16, &[note(25, Instrument::Saw, 5)])).unwrap();
+        let row = mapped.value.rows[0];
+        assert_eq!(
+            (NOTE_START + 1, 4, instrument_id(2, 5)),
+            (row.note, row.octave, row.sfx)
+        );
+        assert_eq!(Row::OFF.note, mapped.value.rows[1].note);
+        assert_eq!(Row::default().note, mapped.value.rows[2].note);
+        assert!(mapped.approximations.is_empty());
+    }
+
+    #[test]
+    fn track_speed_matches_pico8_speed() {
+        let (tempo, speed) = tempo_and_speed(16);
+        let seconds = 2.5 * speed as f64 / tempo as f64;
+        assert!((seconds - 16.0 * 183.0 / 22050.0).abs() < 0.001);
+
+        let slots = [
+            Slot::Pattern { id: 1, speed: 8 },
+            Slot::Pattern { id: 2, speed: 8 },
+            Slot::Pattern { id: 3, speed: 30 },
+        ];
+        assert_eq!(8, track_speed(&[pattern(&[0, 1, 2])], &slots));
+    }
+
+    #[test]
+    fn invalid_sfx_are_skipped() {
+        let mut looped = sfx(16, &[note(24, Instrument::Square, 5)]);
+        looped.loop_start = 40;
+        let cartridge = cart(
+            &[sfx(16, &[note(25, Instrument::Saw, 5)]), looped],
+            &[pattern(&[0, 1])],
+        );
+        let mut report = Report::default();
+        let out = translate(&cartridge, Target::Tic80, &mut report).unwrap();
+
+        // One pattern, for SFX 0: the note, note off, and empty rows.
+        let patterns = format!(
+            "-- <PATTERNS>\n-- 000:500059100000{}\n-- </PATTERNS>",
+            "0".repeat(372)
+        );
+        assert!(out.contains(&patterns), "{}", out);
+        assert!(matches!(
+            report.sfx[1].outcome,
+            crate::report::Outcome::Rejected { .. }
+        ));
+        assert_eq!(
+            vec!["Skipping SFX 1: SFX loop start must be 0–32; 40 is out of range"],
+            report.messages()[..1]
+        );
+    }
+}
@@ -0,0 +1,312 @@
+use super::MusicSfx;
+use crate::music::Pattern;
+use crate::report::{Mapped, Reasons, Rejection, Report};
+use crate::sfx::{Effect, Instrument, Sfx};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+pub fn translate(
+    section: &MusicSfx,
+    report: &mut Report,
+    out: &mut dyn Write,
+) -> crate::Result<()> {
+    let mut wasm4sfxes = Vec::with_capacity(section.sfx.sfxes.len());
+    // Index in `SFX_DATA` and WASM-4 channels of each PICO-8 SFX, or `None` if it wasn't translated.
+    let mut translated_sfx = Vec::with_capacity(section.sfx.sfxes.len());
+    for (i, sfx) in section.sfx.sfxes.iter().enumerate() {
+        let result = map_sfx(sfx);
+        report.record_sfx(i, &result);
+        match result {
+            Ok(mapped) => {
+                translated_sfx.push(Some((wasm4sfxes.len(), mapped.value.channels())));
+                wasm4sfxes.push(mapped.value);
+            }
+            Err(_) => translated_sfx.push(None),
+        }
+    }
+
+    let mut wasm4patterns = Vec::with_capacity(section.music.patterns.len());
+    for (i, pattern) in section.music.patterns.iter().enumerate() {
+        let result = map_music(pattern, &translated_sfx);
+        report.record_pattern(i, &result);
+        if let Ok(mapped) = result {
+            wasm4patterns.push(mapped.value);
+        }
+    }
+
+    // TODO: figure out much better way to emit this code
+    writeln!(out, "//region SFX and music data")?;
+    writeln!(out)?;
+
+    writeln!(out, "const SFX_DATA: &[Sfx] = &[")?;
+    for wasm4sfx in wasm4sfxes {
+        writeln!(out, "    Sfx{{")?;
+        writeln!(
+            out,
+            "        frames_per_tone: {},",
+            wasm4sfx.frames_per_tone
+        )?;
+        if let Some(loop_restart) = wasm4sfx.loop_restart {
+            writeln!(out, "        loop_restart: Some({}),", loop_restart)?;
+        } else {
+            writeln!(out, "        loop_restart: None,")?;
+        }
+        writeln!(out, "        tones: &[")?;
+        for tone in wasm4sfx.tones {
+            writeln!(out, "            Tone{{")?;
+            writeln!(out, "                frequency: {},", tone.frequency)?;
+            writeln!(out, "                duration: {},", tone.duration)?;
+            writeln!(out, "                volume: {},", tone.volume)?;
+            writeln!(out, "                flags: {},", tone.flags)?;
+            writeln!(out, "            }},")?;
+        }
+        writeln!(out, "        ],")?;
+        writeln!(out, "    }},")?;
+    }
+    writeln!(out, "];")?;
+    writeln!(out)?;
+
+    writeln!(out, "const MUSIC_DATA: &[Pattern] = &[")?;
+    for wasm4pattern in wasm4patterns {
+        writeln!(out, "    Pattern{{")?;
+        writeln!(out, "        loop_start: {},", wasm4pattern.loop_start)?;
+        writeln!(out, "        loop_back: {},", wasm4pattern.loop_back)?;
+        writeln!(out, "        stop_at_end: {},", wasm4pattern.stop_at_end)?;
+        writeln!(out, "        sfxes: &[")?;
+        for index in wasm4pattern.sfx_indices {
+            writeln!(out, "            &SFX_DATA[{}],", index)?;
+        }
+        writeln!(out, "        ],")?;
+        writeln!(out, "    }},")?;
+    }
+    writeln!(out, "];")?;
+    writeln!(out)?;
+
+    writeln!(out, "//endregion SFX and music data")?;
+    Ok(())
+}
+
+fn map_music(
+    pattern: &Pattern,
+    translated_sfx: &[Option<(usize, BTreeSet<u32>)>],
+) -> Result<Mapped<Wasm4Pattern>, Rejection> {
+    // TODO: should we skip empty patterns? Does PICO-8 actually play them?
+    if !pattern.enabled() {
+        return Err(Rejection::from("No channels in this pattern"));
+    }
+
+    // WASM-4 only has one channel of each type, so PICO-8 channels that share one will cut each other off.
+    // `SFX_DATA` only holds translated SFX, so a pattern can't play any others.
+    let mut reasons = Reasons::default();
+    let mut approximations = Reasons::default();
+    let mut claimed_by = BTreeMap::new();
+    let mut sfx_indices = vec![];
+    for (i, channel) in pattern.channels.iter().enumerate() {
+        if !channel.enabled() {
+            continue;
+        }
+        let sfx_id = u8::from(channel.sfx_id) as usize;
+        match &translated_sfx[sfx_id] {
+            None => reasons.push(format!(
+                "Channel {} plays SFX {}, which was not translated",
+                i, sfx_id
+            )),
+            Some((index, wasm4_channels)) => {
+                sfx_indices.push(*index);
+                for &wasm4_channel in wasm4_channels {
+                    if let Some(other) = claimed_by.insert(wasm4_channel, i) {
+                        approximations.push(format!(
+                            "Channels {} and {} both use the WASM-4 {} channel",
+                            other,
+                            i,
+                            Wasm4Tone::channel_name(wasm4_channel)
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    if !reasons.is_empty() {
+        return Err(Rejection::from(reasons));
+    }
+
+    Ok(Mapped {
+        value: Wasm4Pattern {
+            loop_start: pattern.loop_start(),
+            loop_back: pattern.loop_back(),
+            stop_at_end: pattern.stop_at_end(),
+            sfx_indices,
+        },
+        approximations: approximations.into_vec(),
+    })
+}
+
+fn map_sfx(sfx: &Sfx) -> Result<Mapped<Wasm4Sfx>, Rejection> {
+    // TODO: we can't actually skip every silent SFX,
+    //  as they might be used by music as spacers.
+    //  But for now, skip them so we don't have empty SFXes everywhere.
+    if !sfx.enabled() {
+        return Err(Rejection::from("No notes in this SFX"));
+    }
+
+    // Check preconditions for entire SFX.
+    // Loop points past the end of the SFX would make the notes below out of range.
+    let mut reasons = Reasons::default();
+    for e in sfx.validate() {
+        reasons.push(e.to_string());
+    }
+    if !reasons.is_empty() {
+        return Err(Rejection::from(reasons));
+    }
+    if !sfx.speed.is_multiple_of(2) {
+        reasons.push("Odd PICO-8 speeds map to non-integer numbers of WASM-4 frames, and cannot be represented".to_string());
+    }
+    if sfx.switches.buzz {
+        reasons.push("Unsupported SFX filter: buzz".to_string());
+    }
+    if sfx.switches.noiz {
+        reasons.push("Unsupported SFX filter: noiz".to_string());
+    }
+    if sfx.switches.detune() != 0 {
+        reasons.push("Unsupported SFX filter: detune".to_string());
+    }
+    if sfx.switches.reverb() != 0 {
+        reasons.push("Unsupported SFX filter: reverb".to_string());
+    }
+    if sfx.switches.dampen() != 0 {
+        reasons.push("Unsupported SFX filter: dampen".to_string());
+    }
+
+    // Get SFX size and optional loop restart point.
+    let (loop_restart, size) = match (sfx.loop_start, sfx.loop_end) {
+        (0, 0) => (None, sfx.notes.len()),
+        (size, 0) => (None, size as usize),
+        (loop_restart, size) => (Some(loop_restart as usize), size as usize),
+    };
+
+    let frames_per_tone = sfx.speed / 2;
+    let mut tones = Vec::with_capacity(size);
+    for note in sfx.notes[..size].iter() {
+        // TODO: specify channel to use for pulse/square tones
+        let flags = match note.instrument() {
+            Instrument::Triangle => 0b10,
+            // pulse channel 1, default duty cycle
+            Instrument::Pulse => 0b00,
+            // pulse channel 2, 50% duty cycle
+            Instrument::Square => 0b10_01,
+            Instrument::Noise => 0b11,
+            instrument @ (Instrument::TiltedSaw
+            | Instrument::Saw
+            | Instrument::Organ
+            | Instrument::Phaser
+            | Instrument::Sfx(_)) => {
+                reasons.push(format!("Unsupported instrument: {:?}", instrument));
+                continue;
+            }
+        };
+        if note.effect() != Effect::None {
+            reasons.push(format!("Unsupported effect: {:?}", note.effect()));
+        }
+        tones.push(Wasm4Tone {
+            // TODO: emulate drop effect using frequency sweep?
+            frequency: note.pitch().frequency(),
+            // TODO: emulate other filters/effects using ADSR params?
+            duration: frames_per_tone as u32,
+            volume: (u8::from(note.volume()) as u32) * 100 / 7,
+            flags,
+        });
+    }
+
+    if !reasons.is_empty() {
+        return Err(Rejection::from(reasons));
+    }
+    Ok(Mapped {
+        value: Wasm4Sfx {
+            frames_per_tone,
+            loop_restart,
+            tones,
+        },
+        approximations: vec![],
+    })
+}
+
+#[derive(Debug)]
+struct Wasm4Sfx {
+    frames_per_tone: u8,
+    loop_restart: Option<usize>,
+    tones: Vec<Wasm4Tone>,
+}
+
+/// Parameters for a WASM-4 `tone(…)` call.
+/// See https://wasm4.org/docs/reference/functions/#tone-frequency-duration-volume-flags
+#[derive(Debug)]
+struct Wasm4Tone {
+    frequency: u32,
+    duration: u32,
+    volume: u32,
+    flags: u32,
+}
+
+impl Wasm4Sfx {
+    /// WASM-4 channels this SFX plays on.
+    fn channels(&self) -> BTreeSet<u32> {
+        self.tones.iter().map(|tone| tone.channel()).collect()
+    }
+}
+
+impl Wasm4Tone {
+    fn channel(&self) -> u32 {
+        self.flags & 0b11
+    }
+
+    fn channel_name(channel: u32) -> &'static str {
+        match channel {
+            0 => "pulse 1",
+            1 => "pulse 2",
+            2 => "triangle",
+            _ => "noise",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Wasm4Pattern {
+    loop_start: bool,
+    loop_back: bool,
+    stop_at_end: bool,
+    /// Indices in `SFX_DATA` of the SFX on each enabled channel.
+    sfx_indices: Vec<usize>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::report::{Outcome, Report};
+    use crate::sfx::Instrument;
+    use crate::translate::tests::{cart, note, pattern, sfx};
+    use crate::translate::{translate, Target};
+
+    #[test]
+    fn patterns_index_translated_sfx_and_reject_the_rest() {
+        let cartridge = cart(
+            &[
+                sfx(16, &[note(24, Instrument::Saw, 5)]),
+                sfx(16, &[note(24, Instrument::Square, 5)]),
+            ],
+            &[pattern(&[1]), pattern(&[1, 0])],
+        );
+        let mut report = Report::default();
+        let out = translate(&cartridge, Target::Wasm4, &mut report).unwrap();
+
+        // SFX 1 is the only one in SFX_DATA, and pattern 0's disabled channels are left out.
+        let music = &out[out.find("const MUSIC_DATA").unwrap()..];
+        assert_eq!(music.matches("&SFX_DATA[").count(), 1);
+        assert!(music.contains("        sfxes: &[\n            &SFX_DATA[0],\n        ],\n"));
+        assert!(matches!(report.patterns[0].outcome, Outcome::Translated));
+        assert!(matches!(
+            &report.patterns[1].outcome,
+            Outcome::Rejected { reasons }
+                if reasons == &["Channel 1 plays SFX 0, which was not translated"]
+        ));
+    }
+}
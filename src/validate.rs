@@ -0,0 +1,21 @@
+use pico8_rom::rom;
+use pico8_rom::sfx::Section as SfxSection;
+use std::path::Path;
+
+/// Report everything in a ROM that can't be decoded correctly.
+/// Music patterns use every bit, so only SFX can be malformed.
+pub fn validate(path: &Path) -> anyhow::Result<()> {
+    let sfx = rom::read_section::<SfxSection>(path, rom::SFX.start as u64)?;
+    let errors = sfx.validate();
+    for (i, e) in errors.iter() {
+        println!("SFX {}: {}", i, e);
+    }
+    if !errors.is_empty() {
+        anyhow::bail!(
+            "{} problem(s) found in {}",
+            errors.len(),
+            path.to_string_lossy()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,124 @@
+//! JavaScript bindings, enabled by the `wasm` feature.
+//! Every function takes the bytes of a `.p8.rom` file.
+
+use crate::cartridge::Cartridge;
+use crate::gfx;
+use crate::report::Report;
+use crate::translate::{self, Target};
+use wasm_bindgen::prelude::*;
+
+fn cartridge(rom: &[u8]) -> Result<Cartridge, JsError> {
+    Ok(Cartridge::from_slice(rom)?)
+}
+
+/// Music section as JSON, in the same form as `dump music --format json`.
+#[wasm_bindgen(js_name = musicJson)]
+pub fn music_json(rom: &[u8]) -> Result<String, JsError> {
+    Ok(serde_json::to_string(&cartridge(rom)?.music()?)?)
+}
+
+/// SFX section as JSON, in the same form as `dump sfx --format json`.
+#[wasm_bindgen(js_name = sfxJson)]
+pub fn sfx_json(rom: &[u8]) -> Result<String, JsError> {
+    Ok(serde_json::to_string(&cartridge(rom)?.sfx()?)?)
+}
+
+/// Whole sprite sheet as a PNG image.
+#[wasm_bindgen(js_name = spriteSheetPng)]
+pub fn sprite_sheet_png(rom: &[u8]) -> Result<Vec<u8>, JsError> {
+    let size = gfx::SHEET_SIZE as u32;
+    let pixels = gfx::sprite_sheet(&cartridge(rom)?)?;
+    Ok(gfx::to_png(size, size, &pixels)?)
+}
+
+/// One 8×8 sprite as a PNG image.
+#[wasm_bindgen(js_name = spritePng)]
+pub fn sprite_png(rom: &[u8], sprite: usize) -> Result<Vec<u8>, JsError> {
+    if sprite > 255 {
+        return Err(JsError::new("Sprite numbers must be 0–255"));
+    }
+    Ok(gfx::to_png(8, 8, &gfx::sprite(&cartridge(rom)?, sprite)?)?)
+}
+
+/// Music and SFX translated for a target named as in `translate --target`, such as `wasm4`.
+/// Rejected SFX and patterns are left out, and approximated ones are included;
+/// use the CLI's `--report` to see which are which.
+#[wasm_bindgen]
+pub fn translate(rom: &[u8], target: &str) -> Result<String, JsError> {
+    let target = target.parse::<Target>()?;
+    let mut report = Report::default();
+    translate::translate(&cartridge(rom)?, target, &mut report)
+        .map_err(|e| JsError::new(&e.to_string()))
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    #[wasm_bindgen_test]
+    fn blank_rom_converts() {
+        let rom = vec![0; Cartridge::LEN];
+        assert!(sfx_json(&rom).unwrap().starts_with("{\"sfxes\":["));
+        assert!(music_json(&rom).unwrap().starts_with("{\"patterns\":["));
+        assert!(sprite_sheet_png(&rom).unwrap().starts_with(b"\x89PNG"));
+        assert!(translate(&rom, "wasm4").unwrap().contains("SFX_DATA"));
+    }
+
+    #[wasm_bindgen_test]
+    fn wrong_length_is_an_error() {
+        assert!(sfx_json(&[0; 10]).is_err());
+    }
+}
+
+/// The same entry points on the host. `JsError` can only be created in JavaScript,
+/// so only calls that succeed are tested here.
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod host_tests {
+    use super::*;
+    use crate::music::Section as MusicSection;
+    use crate::rom::{GFX, MUSIC, SFX};
+    use crate::sfx::Section as SfxSection;
+    use packed_struct::PackedStruct;
+
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![0; Cartridge::LEN];
+        // One red pixel in the top-left corner of sprite 1.
+        rom[GFX.start + 4] = 0x08;
+        // SFX 0 plays C2 on a square wave at full volume.
+        rom[SFX.start] = 24 | 3 << 6;
+        rom[SFX.start + 1] = 7 << 1;
+        rom[SFX.start + 65] = 16;
+        rom
+    }
+
+    #[test]
+    fn json_holds_the_sections() {
+        let sfx: SfxSection = serde_json::from_str(&sfx_json(&rom()).unwrap()).unwrap();
+        assert_eq!(sfx.pack().unwrap(), rom()[SFX]);
+        let music: MusicSection = serde_json::from_str(&music_json(&rom()).unwrap()).unwrap();
+        assert_eq!(music.pack().unwrap(), rom()[MUSIC]);
+    }
+
+    #[test]
+    fn pngs_hold_the_sprites() {
+        let (width, height, pixels) = gfx::from_png(&sprite_sheet_png(&rom()).unwrap()).unwrap();
+        assert_eq!((width, height), (128, 128));
+        assert_eq!(pixels[8..10], [8, 0]);
+        let (width, height, pixels) = gfx::from_png(&sprite_png(&rom(), 1).unwrap()).unwrap();
+        assert_eq!((width, height), (8, 8));
+        assert_eq!(pixels[..2], [8, 0]);
+    }
+
+    #[test]
+    fn targets_are_named_as_in_the_cli() {
+        assert!(translate(&rom(), "wasm4").unwrap().contains("SFX_DATA"));
+        assert!(translate(&rom(), "TIC80").unwrap().contains("-- <SFX>"));
+        assert!(translate(&rom(), "hugedriver")
+            .unwrap()
+            .contains("order_cnt"));
+        assert!(translate(&rom(), "famitracker")
+            .unwrap()
+            .starts_with("# FamiTracker text export"));
+    }
+}